@@ -1,4 +1,5 @@
 import os
+import sys
 import time
 
 from tqdm import tqdm
@@ -8,6 +9,10 @@ import torch
 import numpy as np
 
 
+# Gradient algorithm: auto, naive, alg1, cancel, cancel_order or order
+ALGORITHM = sys.argv[1] if len(sys.argv) > 1 else "auto"
+
+
 def circuit_iterator():
     for i in tqdm(range(200)):
         path = f"mc2021/{i:03}.nnf"
@@ -31,7 +36,7 @@ def bench_wmc_grad(n=12):
     for _, c in circuit_iterator():
         for i in range(n):
             weights = get_weights(c.nb_vars()).tolist()
-            result, grad, t = c._wmc_grad(weights)
+            result, grad, t = c._wmc_grad(weights, ALGORITHM)
             timings[i].append(t)
     print_timings(timings)
 
@@ -46,7 +51,7 @@ def bench_log_grad(n=3):
             pos_weights = pos_weights.log().tolist()
             neg_weights = neg_weights.log().tolist()
 
-            result, pos_grad, neg_grad, t = c._log_grad(pos_weights, neg_weights)
+            result, pos_grad, neg_grad, t = c._log_grad(pos_weights, neg_weights, ALGORITHM)
             timings[i].append(t)
     print_timings(timings)
 
@@ -61,7 +66,7 @@ def bench_fuzzy_grad(n=3):
             pos_weights = pos_weights.tolist()
             neg_weights = neg_weights.tolist()
 
-            result, pos_grad, neg_grad, t = c._fuzzy_grad(pos_weights, neg_weights)
+            result, pos_grad, neg_grad, t = c._fuzzy_grad(pos_weights, neg_weights, ALGORITHM)
             timings[i].append(t)
     print_timings(timings)
 
@@ -74,7 +79,7 @@ def bench_bool_grad(n=3):
         pos_weights, neg_weights = weights.tolist(), (~weights).tolist()
 
         for i in range(n):
-            t = c._bool_grad(pos_weights, neg_weights)[-1]
+            t = c._bool_grad(pos_weights, neg_weights, ALGORITHM)[-1]
             timings[i].append(t)
     print_timings(timings)
 
@@ -22,30 +22,27 @@ class CircuitFunction(torch.autograd.Function):
         # weights: (nb_vars, batch_size)
         device, dtype = pos_weights.device, pos_weights.dtype
         pos_weights, neg_weights = to_numpy(pos_weights).astype(np.float64), to_numpy(neg_weights).astype(np.float64)
-        result, pos_grads, neg_grads = circuit._wmc_np_grad(pos_weights, neg_weights)
-        ctx.save_for_backward(
-            to_torch(pos_grads, device, dtype),
-            to_torch(neg_grads, device, dtype)
-        )
+        result, tape = circuit.forward(pos_weights, neg_weights)
+        ctx.circuit, ctx.tape = circuit, tape
         return to_torch(result, device, dtype)
 
     @staticmethod
     def backward(ctx, grad_output):
-        pos_grads, neg_grads = ctx.saved_tensors
-        return pos_grads * grad_output, neg_grads * grad_output, None
+        # The tape yields vector-Jacobian products, so gradients are only computed when requested.
+        device, dtype = grad_output.device, grad_output.dtype
+        grad_output = grad_output.detach().cpu().numpy().astype(np.float64)
+        pos_grads, neg_grads = ctx.circuit.backward(ctx.tape, grad_output)
+        return to_torch(pos_grads, device, dtype), to_torch(neg_grads, device, dtype), None
 
 
 class LogCircuitFunction(CircuitFunction):
     @staticmethod
     def forward(ctx, pos_weights, neg_weights, circuit):
         device, dtype = pos_weights.device, pos_weights.dtype
-        log_wmc = circuit._log_wmc_np_grad(to_numpy(pos_weights), to_numpy(neg_weights))
-        result, pos_grads, neg_grads = (to_torch(t, device, dtype) for t in log_wmc)
-
-        pos_grads = (pos_grads - result + pos_weights).exp()
-        neg_grads = (neg_grads - result + neg_weights).exp()
-        ctx.save_for_backward(pos_grads, neg_grads)
-        return result
+        pos_np, neg_np = to_numpy(pos_weights).astype(np.float32), to_numpy(neg_weights).astype(np.float32)
+        result, tape = circuit.log_forward(pos_np, neg_np)
+        ctx.circuit, ctx.tape = circuit, tape
+        return to_torch(result, device, dtype)
 
 
 class CircuitModule(torch.nn.Module):
@@ -71,7 +68,7 @@ class CircuitModule(torch.nn.Module):
                 wmc = self.circuit.wmc(weights.tolist())
             else:
                 assert weights.ndim == 2
-                wmc = self.circuit.wmc_np(weights.numpy())
+                wmc = self.circuit.wmc_np(to_numpy(weights).astype(np.float64))
             return torch.tensor(wmc, dtype=torch.float64)
 
     def log_forward(self, weights: torch.Tensor, neg_weights: Optional[torch.Tensor] = None):
@@ -81,5 +78,9 @@ class CircuitModule(torch.nn.Module):
             return LogCircuitFunction.apply(weights, neg_weights, self.circuit)
         else:
             assert neg_weights is None, "not implemented"
-            log_wmc = self.circuit.log_wmc(weights.tolist())
+            if weights.ndim == 1:
+                log_wmc = self.circuit.log_wmc(weights.tolist())
+            else:
+                assert weights.ndim == 2
+                log_wmc = self.circuit.log_wmc_np(to_numpy(weights).astype(np.float64))
             return torch.tensor(log_wmc, dtype=torch.float64)
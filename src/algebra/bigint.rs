@@ -0,0 +1,42 @@
+use num_bigint::BigInt;
+use num_traits::{One, Zero};
+use crate::algebra::{Semiring, Ring, Field};
+
+impl Semiring for BigInt {
+    fn zero() -> Self {
+        Zero::zero()
+    }
+
+    fn one() -> Self {
+        One::one()
+    }
+
+    const IDEMPOTENT: bool = false;
+
+    fn has_inverse(&self) -> bool {
+        !Zero::is_zero(self)
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+}
+
+impl Ring for BigInt {}
+
+impl Field for BigInt {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_overflow() {
+        let two = BigInt::from(2);
+        let count = (0..100).fold(<BigInt as Semiring>::one(), |acc, _| acc * &two);
+        assert_eq!(count, BigInt::from(1u128 << 100));
+        assert!(Semiring::is_zero(&(count.clone() - &count)));
+        assert!(count.has_inverse());
+        assert!(!<BigInt as Semiring>::zero().has_inverse());
+    }
+}
@@ -1,7 +1,6 @@
 use std::ops::{Add, Mul, Sub, Div};
 use std::iter::{Sum, Product};
 use crate::algebra::{Semiring, Ring, Field, BoolSemiring};
-use crate::algebra::utils::ln_add_exp;
 
 
 // SEMIRING OPERATIONS
@@ -16,8 +15,8 @@ impl<'a> Add<&'a Self> for BoolSemiring {
 
 
 impl<'a> Sum<&'a Self> for BoolSemiring {
-    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
-        Self(iter.map(|x| x.0).any(|x| x))
+    fn sum<I: Iterator<Item = &'a Self>>(mut iter: I) -> Self {
+        Self(iter.any(|x| x.0))
     }
 }
 
@@ -30,8 +29,8 @@ impl<'a> Mul<&'a Self> for BoolSemiring {
 }
 
 impl<'a> Product<&'a Self> for BoolSemiring {
-    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
-        Self(iter.map(|x| x.0).all(|x| x))
+    fn product<I: Iterator<Item = &'a Self>>(mut iter: I) -> Self {
+        Self(iter.all(|x| x.0))
     }
 }
 
@@ -47,7 +46,7 @@ impl Semiring for BoolSemiring {
     const IDEMPOTENT: bool = false;
 
     fn has_inverse(&self) -> bool {
-        self.0 == true
+        self.0
     }
 }
 
@@ -62,7 +61,7 @@ impl Ring for BoolSemiring {
 impl<'a> Sub<&'a Self> for BoolSemiring {
     type Output = Self;
 
-    fn sub(self, rhs: &'a Self) -> Self::Output {
+    fn sub(self, _rhs: &'a Self) -> Self::Output {
         panic!("Subtraction not defined for BoolSemiring")
     }
 }
@@ -3,12 +3,15 @@ use std::ops::{Add, Mul};
 use std::iter::{Sum, Product};
 use std::rc::Rc;
 use crate::algebra::{Semiring, CircuitSemiring};
-use crate::circuit::circuit::Node;
+use crate::circuit::circuit::{Circuit, NodeKind};
 
 
 // SEMIRING OPERATIONS
 
-fn get_context(lhs: Option<Rc<RefCell<Vec<Node>>>>, rhs: &Option<Rc<RefCell<Vec<Node>>>>) -> Rc<RefCell<Vec<Node>>> {
+// The semiring operations can't fail, so Circuit.circuit_transform checks the size of the result beforehand.
+const NODE_LIMIT: &str = "Circuit exceeds u32 node indices";
+
+fn get_context(lhs: Option<Rc<RefCell<Circuit>>>, rhs: &Option<Rc<RefCell<Circuit>>>) -> Rc<RefCell<Circuit>> {
     match (lhs, rhs) {
         (Some(lhs), None) => lhs,
         (None, Some(rhs)) => rhs.clone(),
@@ -28,9 +31,7 @@ impl<'a> Add<&'a Self> for CircuitSemiring {
 
     fn add(self, rhs: &'a Self) -> Self::Output {
         let context = get_context(self.context, &rhs.context);
-        let new_node = Node::Or(vec![self.ix, rhs.ix]);
-        let ix: usize = context.borrow().len();
-        context.borrow_mut().push(new_node);
+        let ix: usize = context.borrow_mut().push(NodeKind::Or, &[self.ix, rhs.ix]).expect(NODE_LIMIT);
         Self {ix, context: Some(context)}
     }
 }
@@ -42,12 +43,10 @@ impl<'a> Sum<&'a Self> for CircuitSemiring {
             acc_children.push(x.ix);
             (Some(context), acc_children)
         });
-        if children.len() == 0 {
+        if children.is_empty() {
             return Self::zero();
         }
-        let new_node = Node::Or(children);
-        let ix: usize = context.as_ref().unwrap().borrow().len();
-        context.as_ref().unwrap().borrow_mut().push(new_node);
+        let ix: usize = context.as_ref().unwrap().borrow_mut().push(NodeKind::Or, &children).expect(NODE_LIMIT);
         Self {ix, context}
     }
 }
@@ -57,9 +56,7 @@ impl<'a> Mul<&'a Self> for CircuitSemiring {
 
     fn mul(self, rhs: &'a Self) -> Self::Output {
         let context = get_context(self.context, &rhs.context);
-        let new_node = Node::And(vec![self.ix, rhs.ix]);
-        let new_ix: usize = context.borrow().len();
-        context.borrow_mut().push(new_node);
+        let new_ix: usize = context.borrow_mut().push(NodeKind::And, &[self.ix, rhs.ix]).expect(NODE_LIMIT);
         Self {ix: new_ix, context: Some(context)}
     }
 }
@@ -71,12 +68,10 @@ impl<'a> Product<&'a Self> for CircuitSemiring {
             acc_children.push(x.ix);
             (Some(context), acc_children)
         });
-        if children.len() == 0 {
+        if children.is_empty() {
             return Self::one();
         }
-        let new_node = Node::And(children);
-        let ix: usize = context.as_ref().unwrap().borrow().len();
-        context.as_ref().unwrap().borrow_mut().push(new_node);
+        let ix: usize = context.as_ref().unwrap().borrow_mut().push(NodeKind::And, &children).expect(NODE_LIMIT);
         Self {ix, context}
 
     }
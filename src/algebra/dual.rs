@@ -0,0 +1,111 @@
+use std::ops::{Add, Mul, Sub, Div};
+use std::iter::{Sum, Product};
+use crate::algebra::{Semiring, Ring, Field, DualSemiring};
+
+// Dual numbers a + b ε with ε² = 0, for forward-mode differentiation.
+// The first component is the value, the second the tangent.
+
+
+// SEMIRING OPERATIONS
+
+impl<'a, T: Field> Add<&'a Self> for DualSemiring<T> {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0 + &rhs.0, self.1 + &rhs.1)
+    }
+}
+
+impl<'a, T: Field> Sum<&'a Self> for DualSemiring<T> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a, T: Field> Mul<&'a Self> for DualSemiring<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        let tangent = self.0.clone() * &rhs.1 + &(self.1 * &rhs.0);
+        Self(self.0 * &rhs.0, tangent)
+    }
+}
+
+impl<'a, T: Field> Product<&'a Self> for DualSemiring<T> {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl<T: Field> Semiring for DualSemiring<T> {
+    fn zero() -> Self {
+        Self(T::zero(), T::zero())
+    }
+
+    fn one() -> Self {
+        Self(T::one(), T::zero())
+    }
+
+    const IDEMPOTENT: bool = false;
+
+    fn has_inverse(&self) -> bool {
+        self.0.has_inverse()
+    }
+}
+
+// RING OPERATIONS
+
+impl<T: Field> Ring for DualSemiring<T> {}
+
+impl<'a, T: Field> Sub<&'a Self> for DualSemiring<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0 - &rhs.0, self.1 - &rhs.1)
+    }
+}
+
+// FIELD OPERATIONS
+
+impl<T: Field> Field for DualSemiring<T> {}
+
+impl<'a, T: Field> Div<&'a Self> for DualSemiring<T> {
+    type Output = Self;
+
+    // (a + b ε) / (c + d ε) = a / c + (b c - a d) / c² ε
+    fn div(self, rhs: &'a Self) -> Self::Output {
+        let tangent = (self.1 * &rhs.0 - &(self.0.clone() * &rhs.1)) / &(rhs.0.clone() * &rhs.0);
+        Self(self.0 / &rhs.0, tangent)
+    }
+}
+
+// UTILITY
+
+impl<T> From<(T, T)> for DualSemiring<T> {
+    fn from(x: (T, T)) -> Self {
+        Self(x.0, x.1)
+    }
+}
+
+impl<T> From<DualSemiring<T>> for (T, T) {
+    fn from(x: DualSemiring<T>) -> Self {
+        (x.0, x.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivatives_of_arithmetic() {
+        // x = 3 and y = 2, both with tangent along x.
+        let (x, y) = (DualSemiring(3., 1.), DualSemiring(2., 0.));
+        assert_eq!(x.clone() + &y, DualSemiring(5., 1.));
+        assert_eq!(x.clone() - &y, DualSemiring(1., 1.));
+        assert_eq!(x.clone() * &x, DualSemiring(9., 6.));
+        assert_eq!(y.clone() / &x, DualSemiring(2. / 3., -2. / 9.));
+        assert_eq!(x.clone() * &DualSemiring::one(), x);
+        assert!(!DualSemiring(0., 1.).has_inverse());
+    }
+}
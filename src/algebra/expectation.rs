@@ -0,0 +1,144 @@
+use std::ops::{Add, Mul};
+use std::iter::{Sum, Product};
+use crate::algebra::{Semiring, ExpectationSemiring, SecondOrderExpectationSemiring};
+
+// First- and second-order expectation semirings.
+// C.f. Li and Eisner. “First- and Second-Order Expectation Semirings
+// with Applications to Minimum-Risk Training on Translation Forests.”
+
+
+impl<T: Semiring> ExpectationSemiring<T> {
+    /// Label of a literal with weight p and feature value r.
+    pub fn leaf(p: T, r: &T) -> Self {
+        let pr = p.clone() * r;
+        Self(p, pr)
+    }
+}
+
+impl<T: Semiring> SecondOrderExpectationSemiring<T> {
+    /// Label of a literal with weight p and feature values r and s.
+    pub fn leaf(p: T, r: &T, s: &T) -> Self {
+        let pr = p.clone() * r;
+        let ps = p.clone() * s;
+        let prs = pr.clone() * s;
+        Self(p, pr, ps, prs)
+    }
+}
+
+// FIRST-ORDER SEMIRING OPERATIONS
+
+impl<'a, T: Semiring> Add<&'a Self> for ExpectationSemiring<T> {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0 + &rhs.0, self.1 + &rhs.1)
+    }
+}
+
+impl<'a, T: Semiring> Sum<&'a Self> for ExpectationSemiring<T> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a, T: Semiring> Mul<&'a Self> for ExpectationSemiring<T> {
+    type Output = Self;
+
+    // <p1, r1> * <p2, r2> = <p1 p2, p1 r2 + p2 r1>
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        let r = self.0.clone() * &rhs.1 + &(rhs.0.clone() * &self.1);
+        Self(self.0 * &rhs.0, r)
+    }
+}
+
+impl<'a, T: Semiring> Product<&'a Self> for ExpectationSemiring<T> {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl<T: Semiring> Semiring for ExpectationSemiring<T> {
+    fn zero() -> Self {
+        Self(T::zero(), T::zero())
+    }
+
+    fn one() -> Self {
+        Self(T::one(), T::zero())
+    }
+
+    const IDEMPOTENT: bool = false;
+}
+
+// SECOND-ORDER SEMIRING OPERATIONS
+
+impl<'a, T: Semiring> Add<&'a Self> for SecondOrderExpectationSemiring<T> {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0 + &rhs.0, self.1 + &rhs.1, self.2 + &rhs.2, self.3 + &rhs.3)
+    }
+}
+
+impl<'a, T: Semiring> Sum<&'a Self> for SecondOrderExpectationSemiring<T> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a, T: Semiring> Mul<&'a Self> for SecondOrderExpectationSemiring<T> {
+    type Output = Self;
+
+    // <p1, r1, s1, t1> * <p2, r2, s2, t2> =
+    // <p1 p2, p1 r2 + p2 r1, p1 s2 + p2 s1, p1 t2 + p2 t1 + r1 s2 + r2 s1>
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        let r = self.0.clone() * &rhs.1 + &(rhs.0.clone() * &self.1);
+        let s = self.0.clone() * &rhs.2 + &(rhs.0.clone() * &self.2);
+        let t = self.0.clone() * &rhs.3
+            + &(rhs.0.clone() * &self.3)
+            + &(self.1.clone() * &rhs.2)
+            + &(rhs.1.clone() * &self.2);
+        Self(self.0 * &rhs.0, r, s, t)
+    }
+}
+
+impl<'a, T: Semiring> Product<&'a Self> for SecondOrderExpectationSemiring<T> {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl<T: Semiring> Semiring for SecondOrderExpectationSemiring<T> {
+    fn zero() -> Self {
+        Self(T::zero(), T::zero(), T::zero(), T::zero())
+    }
+
+    fn one() -> Self {
+        Self(T::one(), T::zero(), T::zero(), T::zero())
+    }
+
+    const IDEMPOTENT: bool = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_order_product_rule() {
+        let x = ExpectationSemiring::leaf(0.5, &2.);
+        let y = ExpectationSemiring::leaf(0.25, &4.);
+        let product = x.clone() * &y;
+        assert_eq!((product.0, product.1), (0.125, 0.5 * 1. + 0.25 * 1.));
+        let sum = x + &y;
+        assert_eq!((sum.0, sum.1), (0.75, 2.));
+    }
+
+    #[test]
+    fn second_order_product_rule() {
+        let x = SecondOrderExpectationSemiring::leaf(0.5, &2., &3.);
+        let y = SecondOrderExpectationSemiring::leaf(0.25, &4., &5.);
+        let product = x * &y;
+        // The features add up along a model: 0.125 (2 + 4) and 0.125 (3 + 5), with product 0.125 * 6 * 8.
+        assert_eq!((product.0, product.1, product.2, product.3), (0.125, 0.75, 1., 6.));
+    }
+}
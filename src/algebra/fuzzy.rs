@@ -61,7 +61,7 @@ impl Ring for FuzzySemiring {
 impl<'a> Sub<&'a Self> for FuzzySemiring {
     type Output = Self;
 
-    fn sub(self, rhs: &'a Self) -> Self::Output {
+    fn sub(self, _rhs: &'a Self) -> Self::Output {
         panic!("Fuzzy sub");
     }
 }
@@ -71,7 +71,7 @@ impl Field for FuzzySemiring {}
 impl<'a> Div<&'a Self> for FuzzySemiring {
     type Output = Self;
 
-    fn div(self, rhs: &'a Self) -> Self::Output {
+    fn div(self, _rhs: &'a Self) -> Self::Output {
         panic!("Fuzzy div");
     }
 }
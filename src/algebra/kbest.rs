@@ -0,0 +1,96 @@
+use std::ops::{Add, Mul};
+use std::iter::{Sum, Product};
+use crate::algebra::{Semiring, KBestSemiring};
+
+// k-best semiring, which keeps the k highest-scoring (partial) solutions
+// together with the literals that make up their assignment.
+// The zero and one elements don't know k (k = 0), so k is taken from the other operand.
+
+
+impl KBestSemiring {
+    pub fn leaf(k: usize, weight: f64, lit: i32) -> Self {
+        Self {k, solutions: vec![(weight, vec![lit])]}
+    }
+
+    fn truncate(mut self) -> Self {
+        self.solutions.sort_by(|a, b| b.0.total_cmp(&a.0));
+        if self.k > 0 {
+            self.solutions.truncate(self.k);
+        }
+        self
+    }
+}
+
+// SEMIRING OPERATIONS
+
+impl<'a> Add<&'a Self> for KBestSemiring {
+    type Output = Self;
+
+    fn add(mut self, rhs: &'a Self) -> Self::Output {
+        self.k = self.k.max(rhs.k);
+        self.solutions.extend(rhs.solutions.iter().cloned());
+        self.truncate()
+    }
+}
+
+impl<'a> Sum<&'a Self> for KBestSemiring {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a> Mul<&'a Self> for KBestSemiring {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        let k = self.k.max(rhs.k);
+        let solutions = self.solutions.iter().flat_map(|(lhs_score, lhs_lits)| {
+            rhs.solutions.iter().map(move |(rhs_score, rhs_lits)| {
+                let lits = lhs_lits.iter().chain(rhs_lits).copied().collect();
+                (lhs_score * rhs_score, lits)
+            })
+        }).collect();
+        Self {k, solutions}.truncate()
+    }
+}
+
+impl<'a> Product<&'a Self> for KBestSemiring {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl Semiring for KBestSemiring {
+    fn zero() -> Self {
+        Self {k: 0, solutions: vec![]}
+    }
+
+    fn one() -> Self {
+        Self {k: 0, solutions: vec![(1., vec![])]}
+    }
+
+    const IDEMPOTENT: bool = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_k_best() {
+        let x = KBestSemiring::leaf(2, 0.5, 1) + &KBestSemiring::leaf(2, 0.25, -1);
+        let y = KBestSemiring::leaf(2, 0.9, 2) + &KBestSemiring::leaf(2, 0.1, -2);
+        let product = x.clone() * &y;
+        assert_eq!(product.solutions, vec![(0.45, vec![1, 2]), (0.225, vec![-1, 2])]);
+        let sum = x + &KBestSemiring::leaf(2, 0.75, 3);
+        assert_eq!(sum.solutions, vec![(0.75, vec![3]), (0.5, vec![1])]);
+    }
+
+    #[test]
+    fn zero_and_one_take_k_from_the_other_operand() {
+        let x = KBestSemiring::leaf(1, 0.5, 1);
+        assert_eq!((KBestSemiring::one() * &x).solutions, x.solutions);
+        assert_eq!((KBestSemiring::zero() + &x).solutions, x.solutions);
+        assert!((KBestSemiring::zero() * &x).solutions.is_empty());
+    }
+}
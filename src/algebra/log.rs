@@ -55,11 +55,7 @@ impl Semiring for LogSemiring {
 
 impl Ring for LogSemiring {
     fn negate(&self) -> Self {
-        let result = (-self.0.exp()).ln_1p();
-        if result.is_nan() {
-            panic!("ln_neg({}) = NaN", self.0);
-        }
-        Self(result)
+        Self((-self.0.exp()).ln_1p())
     }
 }
 
@@ -67,11 +63,7 @@ impl<'a> Sub<&'a Self> for LogSemiring {
     type Output = Self;
 
     fn sub(self, rhs: &'a Self) -> Self::Output {
-        let result = (self.0.exp() - rhs.0.exp()).ln();
-        if result.is_nan() {
-            panic!("ln_sub_exp({}, {}) = NaN", self.0, rhs.0);
-        }
-        Self(result) // TODO?
+        Self((self.0.exp() - rhs.0.exp()).ln()) // TODO?
     }
 }
 
@@ -85,9 +77,6 @@ impl<'a> Div<&'a Self> for LogSemiring {
     type Output = Self;
 
     fn div(self, rhs: &'a Self) -> Self::Output {
-        if (self.0 - rhs.0).is_nan() {
-            panic!("ln_div({}, {}) = NaN", self.0, rhs.0);
-        }
         Self(self.0 - rhs.0)
     }
 }
@@ -0,0 +1,141 @@
+use std::fmt::{Debug, Display};
+use std::ops::{Add, Mul, Sub, Div};
+use std::iter::{Sum, Product};
+use num_traits::Float;
+use numpy::ndarray::Array1;
+use crate::algebra::{Semiring, Ring, Field, LogNdarraySemiring};
+use crate::algebra::utils::{ln_add_exp, zip_map};
+
+// Batched log semiring: every lane of the array is an independent example.
+// The zero and one elements are arrays of length one, which broadcast
+// against arrays of any batch size.
+
+
+// SEMIRING OPERATIONS
+
+impl<'a, F: Float + Display + Debug> Add<&'a Self> for LogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, ln_add_exp))
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Sum<&'a Self> for LogNdarraySemiring<F> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Mul<&'a Self> for LogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| x + y))
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Product<&'a Self> for LogNdarraySemiring<F> {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl<F: Float + Display + Debug> Semiring for LogNdarraySemiring<F> {
+    fn zero() -> Self {
+        Self(Array1::from_elem(1, F::neg_infinity()))
+    }
+
+    fn one() -> Self {
+        Self(Array1::zeros(1))
+    }
+
+    const IDEMPOTENT: bool = false;
+
+    // Only invertible when every lane is.
+    fn has_inverse(&self) -> bool {
+        self.0.iter().all(|&x| x != F::neg_infinity())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(|&x| x == F::neg_infinity())
+    }
+}
+
+// RING OPERATIONS
+
+impl<F: Float + Display + Debug> Ring for LogNdarraySemiring<F> {
+    fn negate(&self) -> Self {
+        Self(self.0.mapv(|x| (-x.exp()).ln_1p()))
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Sub<&'a Self> for LogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn sub(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| (x.exp() - y.exp()).ln()))
+    }
+}
+
+// FIELD OPERATIONS
+
+impl<F: Float + Display + Debug> Field for LogNdarraySemiring<F> {}
+
+impl<'a, F: Float + Display + Debug> Div<&'a Self> for LogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn div(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| x - y))
+    }
+}
+
+// UTILITY
+
+impl<F> From<Array1<F>> for LogNdarraySemiring<F> {
+    fn from(x: Array1<F>) -> Self {
+        Self(x)
+    }
+}
+
+impl<F> From<LogNdarraySemiring<F>> for Array1<F> {
+    fn from(x: LogNdarraySemiring<F>) -> Self {
+        x.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use numpy::ndarray::array;
+    use super::*;
+
+    fn close(x: &Array1<f64>, y: &Array1<f64>) -> bool {
+        x.len() == y.len() && x.iter().zip(y).all(|(a, b)| (a - b).abs() < 1e-12)
+    }
+
+    #[test]
+    fn operations_are_lane_wise() {
+        let x = LogNdarraySemiring(array![0.2f64, 0.5].mapv(f64::ln));
+        let y = LogNdarraySemiring(array![0.3f64, 0.25].mapv(f64::ln));
+        assert!(close(&(x.clone() + &y).0, &array![0.5f64, 0.75].mapv(f64::ln)));
+        assert!(close(&(x.clone() * &y).0, &array![0.06f64, 0.125].mapv(f64::ln)));
+        assert!(close(&(x.clone() / &y).0, &array![0.2f64 / 0.3, 2.].mapv(f64::ln)));
+        assert!(close(&x.negate().0, &array![0.8f64, 0.5].mapv(f64::ln)));
+    }
+
+    #[test]
+    fn zero_and_one_broadcast() {
+        let x = LogNdarraySemiring(array![-1f64, -2., -3.]);
+        assert_eq!((x.clone() + &LogNdarraySemiring::zero()).0, x.0);
+        assert_eq!((LogNdarraySemiring::one() * &x).0, x.0);
+        assert_eq!([&x, &x].into_iter().product::<LogNdarraySemiring<f64>>().0, array![-2f64, -4., -6.]);
+    }
+
+    #[test]
+    fn zero_and_inverse_need_every_lane() {
+        let x = LogNdarraySemiring(array![f64::NEG_INFINITY, 0.]);
+        assert!(!x.is_zero());
+        assert!(!x.has_inverse());
+        assert!(LogNdarraySemiring(array![f64::NEG_INFINITY, f64::NEG_INFINITY]).is_zero());
+    }
+}
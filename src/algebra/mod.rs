@@ -1,19 +1,33 @@
 mod float;
 mod log;
 mod int;
+mod bigint;
+mod rational;
 mod circuit;
 mod signed_log;
 mod utils;
 mod bool;
 mod fuzzy;
+mod viterbi;
+mod tropical;
+mod kbest;
+mod expectation;
+mod node;
+mod polynomial;
+mod dual;
+mod ndarray;
+mod log_ndarray;
+mod signed_log_ndarray;
 
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::{Add, Mul, Sub, Div};
 use std::iter::{Sum, Product};
 use std::fmt::Debug;
 use std::rc::Rc;
 use numpy::ndarray::Array1;
-use crate::circuit::circuit::Node;
+use num_bigint::BigInt;
+use crate::circuit::circuit::Circuit;
 use crate::circuit::rcircuit::RNode;
 
 pub trait Semiring:
@@ -28,6 +42,7 @@ pub trait Semiring:
     fn one() -> Self;
 
     // Whether the sum semiring operation is idempotent (a + a = a).
+    #[allow(dead_code)]
     const IDEMPOTENT: bool;
 
     fn has_inverse(&self) -> bool {
@@ -41,7 +56,7 @@ pub trait Semiring:
 
 pub trait Ring: Semiring + for <'a> Sub<&'a Self, Output = Self> {
     fn negate(&self) -> Self {
-        Self::one() - &self
+        Self::one() - self
     }
 }
 
@@ -56,19 +71,56 @@ pub struct LogSemiring (pub f32);
 #[derive(Debug, Clone, PartialEq)]
 pub struct FuzzySemiring(pub f32);
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViterbiSemiring(pub f64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TropicalSemiring(pub f32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KBestSemiring {
+    pub k: usize,
+    pub solutions: Vec<(f64, Vec<i32>)>, // (score, literals), sorted by decreasing score
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SignedLogSemiring (pub bool, pub f32);
 
 
+// Batched semirings, which evaluate one example per array lane
+#[derive(Debug, Clone, PartialEq)]
+pub struct NdarraySemiring<F = f64>(pub Array1<F>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogNdarraySemiring<F = f32>(pub Array1<F>);
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct LogNdarraySemiring(pub Array1<f32>);
+pub struct SignedLogNdarraySemiring<F = f32>(pub Array1<(bool, F)>);
+
+// Expectation semirings: <p, r> and <p, r, s, t>
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectationSemiring<T>(pub T, pub T);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecondOrderExpectationSemiring<T>(pub T, pub T, pub T, pub T);
+
+// Dual numbers <value, tangent> for forward-mode differentiation
+#[derive(Debug, Clone, PartialEq)]
+pub struct DualSemiring<T>(pub T, pub T);
 
 // Higher-order semirings
 #[derive(Debug, Clone, PartialEq)]
 pub struct NodeSemiring(pub Rc<RNode>);
 
+// Provenance polynomials, as a map from monomials to their coefficient
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolynomialSemiring(pub BTreeMap<Vec<i32>, BigInt>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoolPolynomialSemiring(pub BTreeSet<Vec<i32>>);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct CircuitSemiring {
     pub ix: usize,
-    pub context: Option<Rc<RefCell<Vec<Node>>>>, // not proud of this one
+    pub context: Option<Rc<RefCell<Circuit>>>, // not proud of this one
 }
\ No newline at end of file
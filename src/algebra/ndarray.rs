@@ -0,0 +1,134 @@
+use std::fmt::Debug;
+use std::ops::{Add, Mul, Sub, Div};
+use std::iter::{Sum, Product};
+use num_traits::Float;
+use numpy::ndarray::Array1;
+use crate::algebra::{Semiring, Ring, Field, NdarraySemiring};
+use crate::algebra::utils::zip_map;
+
+// Batched real semiring: every lane of the array is an independent example.
+// The zero and one elements are arrays of length one, which broadcast
+// against arrays of any batch size.
+
+
+// SEMIRING OPERATIONS
+
+impl<'a, F: Float + Debug> Add<&'a Self> for NdarraySemiring<F> {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| x + y))
+    }
+}
+
+impl<'a, F: Float + Debug> Sum<&'a Self> for NdarraySemiring<F> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a, F: Float + Debug> Mul<&'a Self> for NdarraySemiring<F> {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| x * y))
+    }
+}
+
+impl<'a, F: Float + Debug> Product<&'a Self> for NdarraySemiring<F> {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl<F: Float + Debug> Semiring for NdarraySemiring<F> {
+    fn zero() -> Self {
+        Self(Array1::zeros(1))
+    }
+
+    fn one() -> Self {
+        Self(Array1::ones(1))
+    }
+
+    const IDEMPOTENT: bool = false;
+
+    // Only invertible when every lane is.
+    fn has_inverse(&self) -> bool {
+        self.0.iter().all(|x| !x.is_zero())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(|x| x.is_zero())
+    }
+}
+
+// RING OPERATIONS
+
+impl<F: Float + Debug> Ring for NdarraySemiring<F> {}
+
+impl<'a, F: Float + Debug> Sub<&'a Self> for NdarraySemiring<F> {
+    type Output = Self;
+
+    fn sub(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| x - y))
+    }
+}
+
+// FIELD OPERATIONS
+
+impl<F: Float + Debug> Field for NdarraySemiring<F> {}
+
+impl<'a, F: Float + Debug> Div<&'a Self> for NdarraySemiring<F> {
+    type Output = Self;
+
+    fn div(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| x / y))
+    }
+}
+
+// UTILITY
+
+impl<F> From<Array1<F>> for NdarraySemiring<F> {
+    fn from(x: Array1<F>) -> Self {
+        Self(x)
+    }
+}
+
+impl<F> From<NdarraySemiring<F>> for Array1<F> {
+    fn from(x: NdarraySemiring<F>) -> Self {
+        x.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use numpy::ndarray::array;
+    use super::*;
+
+    #[test]
+    fn operations_are_lane_wise() {
+        let x = NdarraySemiring(array![1f64, 2., -3.]);
+        let y = NdarraySemiring(array![4f64, -1., 0.5]);
+        assert_eq!((x.clone() + &y).0, array![5., 1., -2.5]);
+        assert_eq!((x.clone() - &y).0, array![-3., 3., -3.5]);
+        assert_eq!((x.clone() * &y).0, array![4., -2., -1.5]);
+        assert_eq!((x.clone() / &y).0, array![0.25, -2., -6.]);
+        assert_eq!(x.negate().0, array![0., -1., 4.]);
+    }
+
+    #[test]
+    fn zero_and_one_broadcast() {
+        let x = NdarraySemiring(array![1f64, 2.]);
+        assert_eq!([&x, &NdarraySemiring::one()].into_iter().sum::<NdarraySemiring>().0, array![2., 3.]);
+        assert_eq!([&x, &NdarraySemiring::zero()].into_iter().product::<NdarraySemiring>().0, array![0., 0.]);
+    }
+
+    #[test]
+    fn zero_and_inverse_need_every_lane() {
+        let x = NdarraySemiring(array![0f64, 2.]);
+        assert!(!x.is_zero());
+        assert!(!x.has_inverse());
+        assert!(NdarraySemiring(array![0f64, 0.]).is_zero());
+        assert!(NdarraySemiring(array![1f64, 2.]).has_inverse());
+    }
+}
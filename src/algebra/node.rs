@@ -0,0 +1,114 @@
+use std::ops::{Add, Mul};
+use std::iter::{Sum, Product};
+use std::rc::Rc;
+use crate::algebra::{Semiring, NodeSemiring};
+use crate::circuit::rcircuit::RNode;
+
+// Symbolic semiring, which builds the arithmetic circuit of the computation.
+// Sums and products with zero and one are simplified away.
+
+
+// SEMIRING OPERATIONS
+
+impl<'a> Add<&'a Self> for NodeSemiring {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        if self.0.is_false() {
+            rhs.clone()
+        } else if rhs.0.is_false() {
+            self
+        } else {
+            Self(RNode::Sum(vec![self.0, rhs.0.clone()]).into())
+        }
+    }
+}
+
+impl<'a> Sum<&'a Self> for NodeSemiring {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        let mut children: Vec<Rc<RNode>> = iter
+            .filter(|x| !x.0.is_false())
+            .map(|x| x.0.clone()).collect();
+        match children.len() {
+            0 => Self::zero(),
+            1 => Self(children.pop().unwrap()),
+            _ => Self(RNode::Sum(children).into()),
+        }
+    }
+}
+
+impl<'a> Mul<&'a Self> for NodeSemiring {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        if self.0.is_false() || rhs.0.is_true() {
+            self
+        } else if rhs.0.is_false() || self.0.is_true() {
+            rhs.clone()
+        } else {
+            Self(RNode::Prod(vec![self.0, rhs.0.clone()]).into())
+        }
+    }
+}
+
+impl<'a> Product<&'a Self> for NodeSemiring {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        let mut children: Vec<Rc<RNode>> = Vec::new();
+        for x in iter {
+            if x.0.is_false() {
+                return Self::zero();
+            } else if !x.0.is_true() {
+                children.push(x.0.clone());
+            }
+        }
+        match children.len() {
+            0 => Self::one(),
+            1 => Self(children.pop().unwrap()),
+            _ => Self(RNode::Prod(children).into()),
+        }
+    }
+}
+
+impl Semiring for NodeSemiring {
+    fn zero() -> Self {
+        Self(RNode::Zero.into())
+    }
+
+    fn one() -> Self {
+        Self(RNode::One.into())
+    }
+
+    const IDEMPOTENT: bool = false;
+}
+
+// UTILITY
+
+impl From<i32> for NodeSemiring {
+    fn from(lit: i32) -> Self {
+        Self(RNode::Val(lit).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_and_one_are_simplified() {
+        let x = NodeSemiring::from(1);
+        assert_eq!((x.clone() + &NodeSemiring::zero()).0, x.0);
+        assert_eq!((NodeSemiring::one() * &x).0, x.0);
+        assert!((x.clone() * &NodeSemiring::zero()).0.is_false());
+        assert!([&NodeSemiring::one(), &NodeSemiring::one()].into_iter().product::<NodeSemiring>().0.is_true());
+        assert_eq!([&x, &NodeSemiring::zero()].into_iter().sum::<NodeSemiring>().0, x.0);
+    }
+
+    #[test]
+    fn builds_sums_and_products() {
+        let (x, y) = (NodeSemiring::from(1), NodeSemiring::from(-2));
+        let sum = x.clone() + &y;
+        assert_eq!(*sum.0, RNode::Sum(vec![x.0.clone(), y.0.clone()]));
+        let product = [&x, &NodeSemiring::one(), &y].into_iter().product::<NodeSemiring>();
+        assert_eq!(*product.0, RNode::Prod(vec![x.0, y.0]));
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Add, Mul};
+use std::iter::{Sum, Product};
+use num_bigint::BigInt;
+use crate::algebra::{Semiring, PolynomialSemiring, BoolPolynomialSemiring};
+
+// Provenance polynomials N[X] and B[X].
+// C.f. Green, Karvounarakis and Tannen. “Provenance Semirings.”
+// A monomial is a sorted list of literals, where repeated literals are exponents.
+
+
+fn mul_monomials(lhs: &[i32], rhs: &[i32]) -> Vec<i32> {
+    let mut result: Vec<i32> = lhs.iter().chain(rhs).copied().collect();
+    result.sort_unstable();
+    result
+}
+
+// N[X] SEMIRING OPERATIONS
+
+impl<'a> Add<&'a Self> for PolynomialSemiring {
+    type Output = Self;
+
+    fn add(mut self, rhs: &'a Self) -> Self::Output {
+        for (monomial, coef) in rhs.0.iter() {
+            *self.0.entry(monomial.clone()).or_default() += coef;
+        }
+        self
+    }
+}
+
+impl<'a> Sum<&'a Self> for PolynomialSemiring {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a> Mul<&'a Self> for PolynomialSemiring {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        let mut result: BTreeMap<Vec<i32>, BigInt> = BTreeMap::new();
+        for (lhs_monomial, lhs_coef) in self.0.iter() {
+            for (rhs_monomial, rhs_coef) in rhs.0.iter() {
+                *result.entry(mul_monomials(lhs_monomial, rhs_monomial)).or_default() += lhs_coef * rhs_coef;
+            }
+        }
+        Self(result)
+    }
+}
+
+impl<'a> Product<&'a Self> for PolynomialSemiring {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl Semiring for PolynomialSemiring {
+    fn zero() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    fn one() -> Self {
+        Self(BTreeMap::from([(vec![], BigInt::from(1))]))
+    }
+
+    const IDEMPOTENT: bool = false;
+}
+
+// B[X] SEMIRING OPERATIONS
+
+impl<'a> Add<&'a Self> for BoolPolynomialSemiring {
+    type Output = Self;
+
+    fn add(mut self, rhs: &'a Self) -> Self::Output {
+        self.0.extend(rhs.0.iter().cloned());
+        self
+    }
+}
+
+impl<'a> Sum<&'a Self> for BoolPolynomialSemiring {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a> Mul<&'a Self> for BoolPolynomialSemiring {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0.iter().flat_map(|lhs_monomial| {
+            rhs.0.iter().map(|rhs_monomial| mul_monomials(lhs_monomial, rhs_monomial))
+        }).collect())
+    }
+}
+
+impl<'a> Product<&'a Self> for BoolPolynomialSemiring {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl Semiring for BoolPolynomialSemiring {
+    fn zero() -> Self {
+        Self(BTreeSet::new())
+    }
+
+    fn one() -> Self {
+        Self(BTreeSet::from([vec![]]))
+    }
+
+    const IDEMPOTENT: bool = true;
+}
+
+// UTILITY
+
+impl From<i32> for PolynomialSemiring {
+    fn from(lit: i32) -> Self {
+        Self(BTreeMap::from([(vec![lit], BigInt::from(1))]))
+    }
+}
+
+impl From<i32> for BoolPolynomialSemiring {
+    fn from(lit: i32) -> Self {
+        Self(BTreeSet::from([vec![lit]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coefficients_and_exponents_add_up() {
+        let (x, y) = (PolynomialSemiring::from(1), PolynomialSemiring::from(-2));
+        let sum = x.clone() + &y;
+        let square = sum.clone() * &sum;
+        assert_eq!(square.0, BTreeMap::from([
+            (vec![-2, -2], BigInt::from(1)),
+            (vec![-2, 1], BigInt::from(2)),
+            (vec![1, 1], BigInt::from(1)),
+        ]));
+        assert_eq!((x.clone() * &PolynomialSemiring::one()).0, x.0);
+        assert!((x * &PolynomialSemiring::zero()).0.is_empty());
+    }
+
+    #[test]
+    fn bool_polynomials_drop_coefficients() {
+        let (x, y) = (BoolPolynomialSemiring::from(1), BoolPolynomialSemiring::from(-2));
+        let sum = x.clone() + &y + &x;
+        let square = sum.clone() * &sum;
+        assert_eq!(square.0, BTreeSet::from([vec![-2, -2], vec![-2, 1], vec![1, 1]]));
+        assert!((x * &BoolPolynomialSemiring::zero()).0.is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+use crate::algebra::{Semiring, Ring, Field};
+
+impl Semiring for BigRational {
+    fn zero() -> Self {
+        Zero::zero()
+    }
+
+    fn one() -> Self {
+        One::one()
+    }
+
+    const IDEMPOTENT: bool = false;
+
+    fn has_inverse(&self) -> bool {
+        !Zero::is_zero(self)
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+}
+
+impl Ring for BigRational {}
+
+impl Field for BigRational {}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use super::*;
+
+    #[test]
+    fn is_exact() {
+        let third = BigRational::new(BigInt::from(1), BigInt::from(3));
+        let sum = [&third, &third, &third].into_iter().fold(<BigRational as Semiring>::zero(), |acc, x| acc + x);
+        assert_eq!(sum, <BigRational as Semiring>::one());
+        assert_eq!(third.clone() * &third, BigRational::new(BigInt::from(1), BigInt::from(9)));
+        assert!(Semiring::is_zero(&(third.clone() - &third)));
+        assert!(third.has_inverse());
+    }
+}
@@ -1,6 +1,7 @@
 use std::iter::{Product, Sum};
 use std::ops::{Add, Mul, Sub, Div};
 use crate::algebra::{Semiring, Ring, Field, SignedLogSemiring};
+use crate::algebra::utils::signed_ln_add_exp;
 
 
 // Logarithmic field which can represent negative numbers.
@@ -15,7 +16,7 @@ impl<'a> Add<&'a Self> for SignedLogSemiring {
 
     fn add(self, rhs: &'a Self) -> Self::Output {
         let sign: bool = if self.1 >= rhs.1 {self.0} else {rhs.0};
-        let val = ln_add_exp(self.1, rhs.1, if self.0 == rhs.0 {1.} else {-1.});
+        let val = signed_ln_add_exp(self.1, rhs.1, if self.0 == rhs.0 {1.} else {-1.});
         Self(sign, val)
     }
 }
@@ -30,7 +31,7 @@ impl<'a> Mul<&'a Self> for SignedLogSemiring {
     type Output = Self;
 
     fn mul(self, rhs: &'a Self) -> Self::Output {
-        Self(self.0 == self.0, self.1 + rhs.1)
+        Self(self.0 == rhs.0, self.1 + rhs.1)
     }
 }
 
@@ -77,15 +78,21 @@ impl<'a> Div<&'a Self> for SignedLogSemiring {
     type Output = Self;
 
     fn div(self, rhs: &'a Self) -> Self::Output {
-        if (self.1 - rhs.1).is_nan() {
-            panic!("ln_div({}, {}) = NaN", self.1, rhs.1);
-        }
         Self(self.0 == rhs.0, self.1 - rhs.1)
     }
 }
 
 // UTILITY
 
+impl SignedLogSemiring {
+    pub fn from_real(x: f32) -> Self {
+        Self(x >= 0., x.abs().ln())
+    }
+
+    pub fn to_real(&self) -> f32 {
+        if self.0 {self.1.exp()} else {-self.1.exp()}
+    }
+}
 
 impl From<(bool, f32)> for SignedLogSemiring {
     fn from(x: (bool, f32)) -> Self {
@@ -105,22 +112,16 @@ impl From<SignedLogSemiring> for (bool, f32) {
     }
 }
 
-
-fn ln_add_exp(x: f32, y: f32, sign: f32) -> f32 {
-    let diff = x - y;
-    let result = if x == f32::NEG_INFINITY {
-        y
-    } else if y == f32::NEG_INFINITY {
-        x
-    } else if diff > 0. {
-        let t = (-diff).exp() * sign;
-        x + t.ln_1p()
-    } else {
-        let t = diff.exp() * sign;
-        y + t.ln_1p()
-    };
-    if result.is_nan() {
-        panic!("ln_add_exp({}, {}) = NaN", x, y);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn products_multiply_the_signs() {
+        let (pos, neg) = (SignedLogSemiring(true, 2f32.ln()), SignedLogSemiring(false, 3f32.ln()));
+        assert!(!(pos.clone() * &neg).0);
+        assert!(!(neg.clone() * &pos).0);
+        assert!((neg.clone() * &neg).0);
+        assert!(((pos * &neg).1 - 6f32.ln()).abs() < 1e-6);
     }
-    result
-}
\ No newline at end of file
+}
@@ -0,0 +1,161 @@
+use std::fmt::{Debug, Display};
+use std::ops::{Add, Mul, Sub, Div};
+use std::iter::{Sum, Product};
+use num_traits::Float;
+use numpy::ndarray::{Array1, Zip};
+use crate::algebra::{Semiring, Ring, Field, SignedLogNdarraySemiring};
+use crate::algebra::utils::{signed_ln_add_exp, zip_map};
+
+// Batched version of the signed log field. Each lane holds a (sign, log-magnitude) pair
+// and follows the same arithmetic as SignedLogSemiring.
+
+
+fn add_lane<F: Float + Display>(x: (bool, F), y: (bool, F)) -> (bool, F) {
+    let sign = if x.1 >= y.1 {x.0} else {y.0};
+    let val = signed_ln_add_exp(x.1, y.1, if x.0 == y.0 {F::one()} else {-F::one()});
+    (sign, val)
+}
+
+// SEMIRING OPERATIONS
+
+impl<'a, F: Float + Display + Debug> Add<&'a Self> for SignedLogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, add_lane))
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Sum<&'a Self> for SignedLogNdarraySemiring<F> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Mul<&'a Self> for SignedLogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| (x.0 == y.0, x.1 + y.1)))
+    }
+}
+
+impl<'a, F: Float + Display + Debug> Product<&'a Self> for SignedLogNdarraySemiring<F> {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl<F: Float + Display + Debug> Semiring for SignedLogNdarraySemiring<F> {
+    fn zero() -> Self {
+        Self(Array1::from_elem(1, (true, F::neg_infinity())))
+    }
+
+    fn one() -> Self {
+        Self(Array1::from_elem(1, (true, F::zero())))
+    }
+
+    const IDEMPOTENT: bool = false;
+
+    // Only invertible when every lane is.
+    fn has_inverse(&self) -> bool {
+        self.0.iter().all(|x| x.1 != F::neg_infinity())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(|x| x.1 == F::neg_infinity())
+    }
+}
+
+// RING OPERATIONS
+
+impl<F: Float + Display + Debug> Ring for SignedLogNdarraySemiring<F> {}
+
+impl<'a, F: Float + Display + Debug> Sub<&'a Self> for SignedLogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn sub(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| add_lane(x, (!y.0, y.1))))
+    }
+}
+
+// FIELD OPERATIONS
+
+impl<F: Float + Display + Debug> Field for SignedLogNdarraySemiring<F> {}
+
+impl<'a, F: Float + Display + Debug> Div<&'a Self> for SignedLogNdarraySemiring<F> {
+    type Output = Self;
+
+    fn div(self, rhs: &'a Self) -> Self::Output {
+        Self(zip_map(self.0, &rhs.0, |x, y| (x.0 == y.0, x.1 - y.1)))
+    }
+}
+
+// UTILITY
+
+impl<F: Float> SignedLogNdarraySemiring<F> {
+    /// Lanes holding real values of either sign.
+    pub fn from_real(x: Array1<F>) -> Self {
+        Self(x.mapv(|v| (v >= F::zero(), v.abs().ln())))
+    }
+}
+
+impl<F: Copy> From<(Array1<bool>, Array1<F>)> for SignedLogNdarraySemiring<F> {
+    fn from(x: (Array1<bool>, Array1<F>)) -> Self {
+        Self(Zip::from(&x.0).and(&x.1).map_collect(|&s, &v| (s, v)))
+    }
+}
+
+impl<F: Copy> From<Array1<F>> for SignedLogNdarraySemiring<F> {
+    fn from(x: Array1<F>) -> Self {
+        Self(x.mapv(|v| (true, v)))
+    }
+}
+
+impl<F: Copy> From<SignedLogNdarraySemiring<F>> for (Array1<bool>, Array1<F>) {
+    fn from(x: SignedLogNdarraySemiring<F>) -> Self {
+        (x.0.mapv(|v| v.0), x.0.mapv(|v| v.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use numpy::ndarray::array;
+    use super::*;
+
+    fn to_real(x: &SignedLogNdarraySemiring<f64>) -> Vec<f64> {
+        x.0.iter().map(|&(sign, v)| if sign { v.exp() } else { -v.exp() }).collect()
+    }
+
+    fn close(x: &[f64], y: &[f64]) -> bool {
+        x.len() == y.len() && x.iter().zip(y).all(|(a, b)| (a - b).abs() < 1e-12)
+    }
+
+    #[test]
+    fn operations_keep_the_sign_per_lane() {
+        let x = SignedLogNdarraySemiring::from_real(array![2f64, -3., 0.5]);
+        let y = SignedLogNdarraySemiring::from_real(array![-0.5f64, -1., 4.]);
+        assert!(close(&to_real(&(x.clone() + &y)), &[1.5, -4., 4.5]));
+        assert!(close(&to_real(&(x.clone() - &y)), &[2.5, -2., -3.5]));
+        assert!(close(&to_real(&(x.clone() * &y)), &[-1., 3., 2.]));
+        assert!(close(&to_real(&(x.clone() / &y)), &[-4., 3., 0.125]));
+    }
+
+    #[test]
+    fn cancellation_gives_zero() {
+        let x = SignedLogNdarraySemiring::from_real(array![2f64, 1.]);
+        let y = SignedLogNdarraySemiring::from_real(array![-2f64, 1.]);
+        let sum = x + &y;
+        assert_eq!(sum.0[0].1, f64::NEG_INFINITY);
+        assert!(close(&to_real(&sum), &[0., 2.]));
+        assert!(!sum.is_zero());
+        assert!(!sum.has_inverse());
+    }
+
+    #[test]
+    fn zero_and_one_broadcast() {
+        let x = SignedLogNdarraySemiring::from_real(array![-1f64, 3.]);
+        assert_eq!((x.clone() + &SignedLogNdarraySemiring::zero()).0, x.0);
+        assert_eq!((SignedLogNdarraySemiring::one() * &x).0, x.0);
+    }
+}
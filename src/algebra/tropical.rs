@@ -0,0 +1,77 @@
+use std::ops::{Add, Mul};
+use std::iter::{Sum, Product};
+use crate::algebra::{Semiring, TropicalSemiring};
+
+// Tropical (max, +) semiring, i.e. the Viterbi semiring in log space.
+
+
+// SEMIRING OPERATIONS
+
+impl<'a> Add<&'a Self> for TropicalSemiring {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0.max(rhs.0))
+    }
+}
+
+impl<'a> Sum<&'a Self> for TropicalSemiring {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a> Mul<&'a Self> for TropicalSemiring {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl<'a> Product<&'a Self> for TropicalSemiring {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl Semiring for TropicalSemiring {
+    fn zero() -> Self {
+        Self(f32::NEG_INFINITY)
+    }
+
+    fn one() -> Self {
+        Self(0.)
+    }
+
+    const IDEMPOTENT: bool = true;
+}
+
+// UTILITY
+
+impl From<f32> for TropicalSemiring {
+    fn from(x: f32) -> Self {
+        Self(x)
+    }
+}
+
+impl From<TropicalSemiring> for f32 {
+    fn from(x: TropicalSemiring) -> Self {
+        x.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_plus() {
+        let (x, y) = (TropicalSemiring(-1.), TropicalSemiring(-2.));
+        assert_eq!((x.clone() + &y).0, -1.);
+        assert_eq!((x.clone() * &y).0, -3.);
+        assert_eq!((x.clone() * &TropicalSemiring::zero()).0, f32::NEG_INFINITY);
+        assert_eq!(std::iter::empty().sum::<TropicalSemiring>().0, f32::NEG_INFINITY);
+        assert_eq!(std::iter::empty().product::<TropicalSemiring>().0, 0.);
+    }
+}
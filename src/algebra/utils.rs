@@ -1,16 +1,51 @@
-pub fn ln_add_exp(x: f32, y: f32) -> f32 {
+use std::fmt::Display;
+use num_traits::Float;
+use numpy::ndarray::{Array1, Zip};
+
+pub fn ln_add_exp<F: Float + Display>(x: F, y: F) -> F {
     let diff = x - y;
-    let result = if x == f32::NEG_INFINITY {
+    if x == F::neg_infinity() {
         y
-    } else if y == f32::NEG_INFINITY {
+    } else if y == F::neg_infinity() {
         x
-    } else if diff > 0. {
+    } else if diff > F::zero() {
         x + (-diff).exp().ln_1p()
     } else {
         y + diff.exp().ln_1p()
-    };
-    if result.is_nan() {
-        panic!("ln_add_exp({}, {}) = NaN", x, y);
     }
-    result
-}
\ No newline at end of file
+}
+
+/// ln(exp(x) + sign * exp(y)), assuming the result keeps the sign of the larger term.
+pub fn signed_ln_add_exp<F: Float + Display>(x: F, y: F, sign: F) -> F {
+    let diff = x - y;
+    if x == F::neg_infinity() {
+        y
+    } else if y == F::neg_infinity() {
+        x
+    } else if diff > F::zero() {
+        let t = (-diff).exp() * sign;
+        x + t.ln_1p()
+    } else {
+        let t = diff.exp() * sign;
+        y + t.ln_1p()
+    }
+}
+
+/// Elementwise binary operation on two batches.
+/// An array of length one (e.g. a semiring zero or one) is broadcast to the other's length.
+/// Other lengths can't mismatch, as the batched entry points check the shapes of the weights.
+pub fn zip_map<T: Copy>(mut lhs: Array1<T>, rhs: &Array1<T>, f: impl Fn(T, T) -> T) -> Array1<T> {
+    if lhs.len() == rhs.len() {
+        Zip::from(&mut lhs).and(rhs).for_each(|x, &y| *x = f(*x, y));
+        lhs
+    } else if lhs.len() == 1 {
+        let x = lhs[0];
+        rhs.mapv(|y| f(x, y))
+    } else if rhs.len() == 1 {
+        let y = rhs[0];
+        lhs.mapv_inplace(|x| f(x, y));
+        lhs
+    } else {
+        unreachable!("Batch size mismatch ({} vs {})", lhs.len(), rhs.len())
+    }
+}
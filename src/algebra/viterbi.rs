@@ -0,0 +1,77 @@
+use std::ops::{Add, Mul};
+use std::iter::{Sum, Product};
+use crate::algebra::{Semiring, ViterbiSemiring};
+
+// Viterbi semiring (max, ×) over probabilities.
+
+
+// SEMIRING OPERATIONS
+
+impl<'a> Add<&'a Self> for ViterbiSemiring {
+    type Output = Self;
+
+    fn add(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0.max(rhs.0))
+    }
+}
+
+impl<'a> Sum<&'a Self> for ViterbiSemiring {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, x| acc + x)
+    }
+}
+
+impl<'a> Mul<&'a Self> for ViterbiSemiring {
+    type Output = Self;
+
+    fn mul(self, rhs: &'a Self) -> Self::Output {
+        Self(self.0 * rhs.0)
+    }
+}
+
+impl<'a> Product<&'a Self> for ViterbiSemiring {
+    fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.fold(Self::one(), |acc, x| acc * x)
+    }
+}
+
+impl Semiring for ViterbiSemiring {
+    fn zero() -> Self {
+        Self(0.)
+    }
+
+    fn one() -> Self {
+        Self(1.)
+    }
+
+    const IDEMPOTENT: bool = true;
+}
+
+// UTILITY
+
+impl From<f64> for ViterbiSemiring {
+    fn from(x: f64) -> Self {
+        Self(x)
+    }
+}
+
+impl From<ViterbiSemiring> for f64 {
+    fn from(x: ViterbiSemiring) -> Self {
+        x.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_times() {
+        let (x, y) = (ViterbiSemiring(0.5), ViterbiSemiring(0.25));
+        assert_eq!((x.clone() + &y).0, 0.5);
+        assert_eq!((x.clone() * &y).0, 0.125);
+        assert_eq!([&x, &y].into_iter().sum::<ViterbiSemiring>().0, 0.5);
+        assert_eq!(std::iter::empty().sum::<ViterbiSemiring>().0, 0.);
+        assert_eq!(std::iter::empty().product::<ViterbiSemiring>().0, 1.);
+    }
+}
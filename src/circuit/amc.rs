@@ -1,6 +1,10 @@
 use std::borrow::Cow;
 use std::borrow::Borrow;
+use std::collections::BTreeSet;
+use std::str::FromStr;
 use std::time::Instant;
+use rand::Rng;
+use rayon::prelude::*;
 use crate::algebra::{Semiring, Field};
 use crate::circuit::weights::Weights;
 use crate::circuit::circuit::{Circuit, Node};
@@ -9,11 +13,11 @@ use crate::circuit::circuit::{Circuit, Node};
 pub(crate) fn amc<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>) -> Vec<T>
 {
     let mut buf: Vec<Cow<T>> = vec![Cow::Owned(T::zero()); circuit.nb_nodes()];
-    for (i, node) in circuit.nodes.iter().enumerate() {
+    for (i, node) in circuit.nodes().enumerate() {
         buf[i] = match node {
-            Node::Or(children) => Cow::Owned(children.iter().map(|&j| buf[j].borrow()).sum()),
-            Node::And(children) => Cow::Owned(children.iter().map(|&j| buf[j].borrow()).product()),
-            Node::Leaf(lit) => weights.val(*lit).unwrap_or_else(|| Cow::Owned(T::one())),
+            Node::Or(children) => Cow::Owned(children.iter().map(|&j| buf[j as usize].borrow()).sum()),
+            Node::And(children) => Cow::Owned(children.iter().map(|&j| buf[j as usize].borrow()).product()),
+            Node::Leaf(lit) => weights.val(lit).unwrap_or_else(|| Cow::Owned(T::one())),
         };
     }
     buf.into_iter().map(|x| x.into_owned()).collect()
@@ -22,47 +26,319 @@ pub(crate) fn amc<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>) ->
 pub(crate) fn amc1<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>) -> T
 {
     let mut buf: Vec<Cow<T>> = vec![Cow::Owned(T::zero()); circuit.nb_nodes()];
-    for (i, node) in circuit.nodes.iter().enumerate() {
+    for (i, node) in circuit.nodes().enumerate() {
         buf[i] = match node {
-            Node::Or(children) => Cow::Owned(children.iter().map(|&j| buf[j].borrow()).sum()),
-            Node::And(children) => Cow::Owned(children.iter().map(|&j| buf[j].borrow()).product()),
-            Node::Leaf(lit) => weights.val(*lit).unwrap_or(Cow::Owned(T::one())),
+            Node::Or(children) => Cow::Owned(children.iter().map(|&j| buf[j as usize].borrow()).sum()),
+            Node::And(children) => Cow::Owned(children.iter().map(|&j| buf[j as usize].borrow()).product()),
+            Node::Leaf(lit) => weights.val(lit).unwrap_or(Cow::Owned(T::one())),
         };
     }
     buf.last().unwrap().clone().into_owned()
 }
 
 
-/// Computes the gradient of the
-/// algebraic model count using backpropagation.
-pub(crate) fn amc_backprop_alg1<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>, grad_weights: &mut impl Weights<T>) -> (T, f64)
+fn eval_node<T: Semiring>(node: Node, buf: &[T], weights: &impl Weights<T>) -> T
+{
+    match node {
+        Node::Or(children) => children.iter().map(|&j| &buf[j as usize]).sum(),
+        Node::And(children) => children.iter().map(|&j| &buf[j as usize]).product(),
+        Node::Leaf(lit) => weights.val(lit).map_or_else(T::one, Cow::into_owned),
+    }
+}
+
+/// Level-parallel AMC. The nodes of a level only have children in lower levels,
+/// so each level is evaluated in parallel on the current thread pool.
+pub(crate) fn amc_par<T, W>(circuit: &Circuit, levels: &[Vec<usize>], weights: &W) -> Vec<T>
+    where T: Semiring + Send + Sync, W: Weights<T> + Sync
+{
+    let mut buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
+    for level in levels {
+        let values: Vec<T> = level.par_iter().map(|&i| eval_node(circuit.node(i), &buf, weights)).collect();
+        for (&i, value) in level.iter().zip(values) {
+            buf[i] = value;
+        }
+    }
+    buf
+}
+
+/// Incremental AMC, which re-evaluates the given nodes and then, in topological order,
+/// only the ancestors of nodes whose value changed. Returns the new value of the root.
+pub(crate) fn amc_update<T: Semiring>(circuit: &Circuit, parents: &[Vec<usize>], weights: &impl Weights<T>, buf: &mut [T], dirty: impl IntoIterator<Item = usize>) -> T
+{
+    let mut dirty: BTreeSet<usize> = dirty.into_iter().collect();
+    while let Some(i) = dirty.pop_first() {
+        let value = eval_node(circuit.node(i), buf, weights);
+        if value != buf[i] {
+            buf[i] = value;
+            dirty.extend(&parents[i]);
+        }
+    }
+    buf.last().unwrap().clone()
+}
+
+/// Most probable explanation, found by backtracking through the maximizing child of every Or node.
+/// Assumes an idempotent semiring, whose sum selects one of its arguments.
+pub(crate) fn amc_mpe<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>, nb_vars: usize) -> (T, Vec<Option<bool>>)
+{
+    let buf: Vec<T> = amc::<T>(circuit, weights);
+    let mut assignment: Vec<Option<bool>> = vec![None; nb_vars];
+    let mut visited: Vec<bool> = vec![false; circuit.nb_nodes()];
+    let mut stack: Vec<usize> = vec![circuit.nb_nodes() - 1];
+
+    while let Some(i) = stack.pop() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        match circuit.node(i) {
+            Node::Or(children) => {
+                if let Some(&j) = children.iter().find(|&&j| buf[j as usize] == buf[i]) {
+                    stack.push(j as usize);
+                }
+            },
+            Node::And(children) => stack.extend(children.iter().map(|&j| j as usize)),
+            Node::Leaf(lit) => assignment[(lit.abs() - 1) as usize] = Some(lit > 0),
+        }
+    }
+    (buf.last().unwrap().clone(), assignment)
+}
+
+/// Samples a model with probability proportional to its weight, going top-down from the root
+/// and picking the child of every Or node in proportion to its value in the forward buffer.
+pub(crate) fn amc_sample<R: Rng>(circuit: &Circuit, buf: &[f64], nb_vars: usize, rng: &mut R) -> Vec<Option<bool>>
+{
+    let mut assignment: Vec<Option<bool>> = vec![None; nb_vars];
+    let mut stack: Vec<usize> = vec![circuit.nb_nodes() - 1];
+
+    while let Some(i) = stack.pop() {
+        match circuit.node(i) {
+            Node::Or(children) => {
+                let mut threshold = rng.gen::<f64>() * buf[i];
+                // Rounding errors can exhaust the threshold, so default to the last possible child.
+                let mut choice = children.iter().rev().map(|&j| j as usize).find(|&j| buf[j] > 0.);
+                for j in children.iter().map(|&j| j as usize) {
+                    threshold -= buf[j];
+                    if threshold < 0. && buf[j] > 0. {
+                        choice = Some(j);
+                        break;
+                    }
+                }
+                stack.extend(choice);
+            },
+            Node::And(children) => stack.extend(children.iter().map(|&j| j as usize)),
+            Node::Leaf(lit) => assignment[(lit.abs() - 1) as usize] = Some(lit > 0),
+        }
+    }
+    assignment
+}
+
+/// Backpropagation algorithms for the gradient of the algebraic model count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GradAlgorithm {
+    Auto,
+    Naive,
+    Alg1,
+    Cancel,
+    CancelOrder,
+    Order,
+}
+
+impl FromStr for GradAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "naive" => Ok(Self::Naive),
+            "alg1" => Ok(Self::Alg1),
+            "cancel" => Ok(Self::Cancel),
+            "cancel_order" => Ok(Self::CancelOrder),
+            "order" => Ok(Self::Order),
+            _ => Err(format!("Unknown gradient algorithm '{}', expected one of auto, naive, alg1, cancel, cancel_order, order", s)),
+        }
+    }
+}
+
+impl GradAlgorithm {
+    /// Resolves Auto from the semiring properties. Idempotent semirings use the ordering,
+    /// others use the cancellation, which falls back on the zeros of nodes without inverse.
+    pub(crate) fn resolve<T: Semiring>(self) -> Self {
+        match self {
+            Self::Auto if T::IDEMPOTENT => Self::Order,
+            Self::Auto => Self::CancelOrder,
+            algorithm => algorithm,
+        }
+    }
+
+    /// Resolves the algorithm for batched semirings, whose zero test and comparisons apply to
+    /// all lanes at once. The zero-counting fallback of cancel_order would then divide by the
+    /// lanes which are zero, and order would compare whole batches, so both use cancel instead.
+    pub(crate) fn batched(self) -> Self {
+        match self {
+            Self::Auto | Self::CancelOrder | Self::Order => Self::Cancel,
+            algorithm => algorithm,
+        }
+    }
+}
+
+/// Computes the gradient of the algebraic model count with the given algorithm.
+pub(crate) fn amc_backprop<T: Field>(algorithm: GradAlgorithm, circuit: &Circuit, weights: &impl Weights<T>, grad_weights: &mut impl Weights<T>) -> (T, f64)
 {
     // First compute the forward pass
     let buf: Vec<T> = amc::<T>(circuit, weights);
+    let duration = backprop(algorithm, circuit, &buf, T::one(), grad_weights);
+    (buf.last().unwrap().clone(), duration)
+}
+
+/// Backpropagates the gradient of the root through the node values of a forward pass,
+/// returning the duration of the backward pass.
+pub(crate) fn backprop<T: Field>(algorithm: GradAlgorithm, circuit: &Circuit, buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
+{
+    match algorithm.resolve::<T>() {
+        GradAlgorithm::Naive => backprop_naive(circuit, buf, grad_root, grad_weights),
+        GradAlgorithm::Alg1 => backprop_alg1(circuit, buf, grad_root, grad_weights),
+        GradAlgorithm::Cancel => backprop_cancel(circuit, buf, grad_root, grad_weights),
+        GradAlgorithm::CancelOrder | GradAlgorithm::Auto => backprop_cancel_order(circuit, buf, grad_root, grad_weights),
+        GradAlgorithm::Order => backprop_order(circuit, buf, grad_root, grad_weights),
+    }
+}
+
+/// Level-parallel gradient of the algebraic model count, see `amc_backprop`.
+pub(crate) fn amc_backprop_par<T, W>(algorithm: GradAlgorithm, circuit: &Circuit, levels: &[Vec<usize>], weights: &W, grad_weights: &mut impl Weights<T>) -> (T, f64)
+    where T: Field + Send + Sync, W: Weights<T> + Sync
+{
+    let buf: Vec<T> = amc_par::<T, W>(circuit, levels, weights);
+    let duration = backprop_par(algorithm, circuit, levels, &buf, T::one(), grad_weights);
+    (buf.last().unwrap().clone(), duration)
+}
+
+/// Level-parallel backpropagation. Going down the levels, the gradient of a node is complete
+/// once the levels above it are done. So each level computes the contributions to the
+/// children of its nodes in parallel, which are then accumulated.
+pub(crate) fn backprop_par<T: Field + Send + Sync>(algorithm: GradAlgorithm, circuit: &Circuit, levels: &[Vec<usize>], buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
+{
+    let t1 = Instant::now();
+    let algorithm = algorithm.resolve::<T>();
+    let mut grad_buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
+    let buf_size = grad_buf.len();
+    grad_buf[buf_size-1] = grad_root;
+
+    for level in levels.iter().rev() {
+        let contributions: Vec<Vec<(usize, T)>> = level.par_iter().map(|&i| match circuit.node(i) {
+            Node::Or(children) => children.iter().map(|&j| (j as usize, grad_buf[i].clone())).collect(),
+            Node::And(children) => {
+                children.iter().zip(and_residuals(algorithm, i, children, buf))
+                    .map(|(&j, residual)| (j as usize, residual * &grad_buf[i]))
+                    .collect()
+            },
+            Node::Leaf(_) => vec![],
+        }).collect();
+        for (j, grad) in contributions.into_iter().flatten() {
+            grad_buf[j] = grad_buf[j].clone() + &grad;
+        }
+    }
+    for (i, node) in circuit.nodes().enumerate() {
+        if let Node::Leaf(lit) = node {
+            let lit_ix = (lit.abs() - 1) as usize;
+            if lit_ix < grad_weights.len() {
+                grad_weights.add(lit, &grad_buf[i]);
+            }
+        }
+    }
+    t1.elapsed().as_secs_f64()
+}
+
+/// Derivatives of an And node with respect to each of its children, following the
+/// sequential backpropagation algorithms.
+fn and_residuals<T: Field>(algorithm: GradAlgorithm, i: usize, children: &[u32], buf: &[T]) -> Vec<T>
+{
+    let naive = || -> Vec<T> {
+        children.iter()
+            .map(|&j| children.iter().flat_map(|&k| if k != j { Some(&buf[k as usize]) } else { None }).product())
+            .collect()
+    };
+    if children.is_empty() {
+        return vec![];
+    }
+    match algorithm {
+        GradAlgorithm::Naive => naive(),
+        GradAlgorithm::Alg1 => {
+            let mut residuals: Vec<T> = Vec::with_capacity(children.len());
+            let mut t = T::one();
+            for j in children.iter().map(|&j| j as usize) {
+                residuals.push(t.clone());
+                t = t * &buf[j];
+            }
+            t = T::one();
+            for (child_ix, j) in children.iter().map(|&j| j as usize).enumerate().rev() {
+                residuals[child_ix] = residuals[child_ix].clone() * &t;
+                t = t * &buf[j];
+            }
+            residuals
+        },
+        GradAlgorithm::Cancel | GradAlgorithm::CancelOrder | GradAlgorithm::Auto if buf[i].has_inverse() => {
+            children.iter().map(|&j| buf[i].clone() / &buf[j as usize]).collect()
+        },
+        GradAlgorithm::Cancel => naive(),
+        GradAlgorithm::CancelOrder | GradAlgorithm::Auto => {
+            let mut residuals: Vec<T> = vec![T::zero(); children.len()];
+            let zeros: Vec<usize> = (0..children.len()).filter(|&c| T::is_zero(&buf[children[c] as usize])).collect();
+            if let [zero] = zeros[..] {
+                residuals[zero] = children.iter().map(|&j| &buf[j as usize]).filter(|x| !T::is_zero(x)).product();
+            }
+            residuals
+        },
+        GradAlgorithm::Order => {
+            let mut nb_max = 0;
+            let mut non_max_prod = T::one();
+            let mut last_max: usize = 0;
+            for (child_ix, j) in children.iter().map(|&j| j as usize).enumerate() {
+                if buf[j] == buf[i] {
+                    nb_max += 1;
+                    last_max = child_ix;
+                    if nb_max > 1 {
+                        non_max_prod = buf[i].clone();
+                        break;
+                    }
+                } else {
+                    non_max_prod = non_max_prod * &buf[j];
+                }
+            }
+            let mut residuals: Vec<T> = vec![buf[i].clone(); children.len()];
+            residuals[last_max] = non_max_prod;
+            residuals
+        },
+    }
+}
+
+/// Computes the gradient of the
+/// algebraic model count using backpropagation.
+pub(crate) fn backprop_alg1<T: Semiring>(circuit: &Circuit, buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
+{
     let t1 = Instant::now();
     // Buffer to store gradients of intermediate nodes
     let mut grad_buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
     // Initialize the gradient of the output node
     let buf_size = grad_buf.len();
-    grad_buf[buf_size-1] = T::one();
+    grad_buf[buf_size-1] = grad_root;
 
     // Backpropagation
-    for (i, node) in circuit.nodes.iter().enumerate().rev() {
+    for (i, node) in circuit.nodes().enumerate().rev() {
         match node {
             Node::Or(children) => {
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     grad_buf[j] = grad_buf[j].clone() + &grad_buf[i];
                 }
             },
             Node::And(children) => {
                 let mut r: Vec<T> = vec![T::one(); children.len()];
                 let mut t = T::one();
-                for (child_ix, &j) in children.iter().enumerate() {
+                for (child_ix, j) in children.iter().map(|&j| j as usize).enumerate() {
                     r[child_ix] = t.clone();
                     t = t * &buf[j];
                 }
                 t = T::one();
-                for (child_ix, &j) in children.iter().enumerate().rev() {
+                for (child_ix, j) in children.iter().map(|&j| j as usize).enumerate().rev() {
                     grad_buf[j] = (r[child_ix].clone() * &t * &grad_buf[i]) + &grad_buf[j];
                     t = t * &buf[j];
                 }
@@ -70,82 +346,76 @@ pub(crate) fn amc_backprop_alg1<T: Semiring>(circuit: &Circuit, weights: &impl W
             Node::Leaf(lit) => {
                 let lit_ix = (lit.abs() - 1) as usize;
                 if lit_ix < grad_weights.len() {
-                    grad_weights.add(*lit, &grad_buf[i]);
+                    grad_weights.add(lit, &grad_buf[i]);
                 }
             },
         }
     }
-    let duration = t1.elapsed().as_secs_f64();
-    (buf.last().unwrap().clone(), duration)
+    t1.elapsed().as_secs_f64()
 }
 
-pub(crate) fn amc_backprop_naive<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>, grad_weights: &mut impl Weights<T>) -> (T, f64)
+pub(crate) fn backprop_naive<T: Semiring>(circuit: &Circuit, buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
 {
-    // First compute the forward pass
-    let buf: Vec<T> = amc::<T>(circuit, weights);
     let t1 = Instant::now();
     // Buffer to store gradients of intermediate nodes
     let mut grad_buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
     // Initialize the gradient of the output node
     let buf_size = grad_buf.len();
-    grad_buf[buf_size-1] = T::one();
+    grad_buf[buf_size-1] = grad_root;
 
     // Backpropagation
-    for (i, node) in circuit.nodes.iter().enumerate().rev() {
+    for (i, node) in circuit.nodes().enumerate().rev() {
         match node {
             Node::Or(children) => {
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     grad_buf[j] = grad_buf[j].clone() + &grad_buf[i];
                 }
             },
             Node::And(children) => {
-                for &j in children.iter() {
-                    let r: T = children.iter().flat_map(|&k| if k != j { Some(&buf[k]) } else { None }).product();
+                for j in children.iter().map(|&j| j as usize) {
+                    let r: T = children.iter().flat_map(|&k| if k as usize != j { Some(&buf[k as usize]) } else { None }).product();
                     grad_buf[j] = (r * &grad_buf[i]) + &grad_buf[j];
                 }
             },
             Node::Leaf(lit) => {
                 let lit_ix = (lit.abs() - 1) as usize;
                 if lit_ix < grad_weights.len() {
-                    grad_weights.add(*lit, &grad_buf[i]);
+                    grad_weights.add(lit, &grad_buf[i]);
                 }
             },
         }
     }
-    let duration = t1.elapsed().as_secs_f64();
-    (buf.last().unwrap().clone(), duration)
+    t1.elapsed().as_secs_f64()
 }
 
 
-pub(crate) fn amc_backprop_cancel_order<T: Field>(circuit: &Circuit, weights: &impl Weights<T>, grad_weights: &mut impl Weights<T>) -> (T, f64)
+pub(crate) fn backprop_cancel_order<T: Field>(circuit: &Circuit, buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
 {
-    // First compute the forward pass
-    let buf: Vec<T> = amc::<T>(circuit, weights);
     let t1 = Instant::now();
     // Buffer to store gradients of intermediate nodes
     let mut grad_buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
     // Initialize the gradient of the output node
     let buf_size = grad_buf.len();
-    grad_buf[buf_size-1] = T::one();
+    grad_buf[buf_size-1] = grad_root;
 
     // Backpropagation
-    for (i, node) in circuit.nodes.iter().enumerate().rev() {
+    for (i, node) in circuit.nodes().enumerate().rev() {
         match node {
             Node::Or(children) => {
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     grad_buf[j] = grad_buf[j].clone() + &grad_buf[i];
                 }
             },
             Node::And(children) => {
                 if buf[i].has_inverse() {
-                    for &j in children {
+                    for j in children.iter().map(|&j| j as usize) {
                         grad_buf[j] = (buf[i].clone() / &buf[j]) * &grad_buf[i] + &grad_buf[j];
                     }
                 } else {
                     let mut nb_zeros = 0;
                     let mut non_zero_prod = T::one();
                     let mut last_zero: usize = 0;
-                    for &j in children {
+                    for j in children.iter().map(|&j| j as usize) {
                         if T::is_zero(&buf[j]) {
                             nb_zeros += 1;
                             last_zero = j;
@@ -164,31 +434,28 @@ pub(crate) fn amc_backprop_cancel_order<T: Field>(circuit: &Circuit, weights: &i
             Node::Leaf(lit) => {
                 let lit_ix = (lit.abs() - 1) as usize;
                 if lit_ix < grad_weights.len() {
-                    grad_weights.add(*lit, &grad_buf[i]);
+                    grad_weights.add(lit, &grad_buf[i]);
                 }
             },
         }
     }
-    let duration = t1.elapsed().as_secs_f64();
-    (buf.last().unwrap().clone(), duration)
+    t1.elapsed().as_secs_f64()
 }
 
-pub(crate) fn amc_backprop_order<T: Semiring>(circuit: &Circuit, weights: &impl Weights<T>, grad_weights: &mut impl Weights<T>) -> (T, f64)
+pub(crate) fn backprop_order<T: Semiring>(circuit: &Circuit, buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
 {
-    // First compute the forward pass
-    let buf: Vec<T> = amc::<T>(circuit, weights);
-    let t1: Instant = Instant::now();
+    let t1 = Instant::now();
     // Buffer to store gradients of intermediate nodes
     let mut grad_buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
     // Initialize the gradient of the output node
     let buf_size = grad_buf.len();
-    grad_buf[buf_size-1] = T::one();
+    grad_buf[buf_size-1] = grad_root;
 
     // Backpropagation
-    for (i, node) in circuit.nodes.iter().enumerate().rev() {
+    for (i, node) in circuit.nodes().enumerate().rev() {
         match node {
             Node::Or(children) => {
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     grad_buf[j] = grad_buf[j].clone() + &grad_buf[i];
                 }
             },
@@ -196,7 +463,7 @@ pub(crate) fn amc_backprop_order<T: Semiring>(circuit: &Circuit, weights: &impl
                 let mut nb_max = 0;
                 let mut non_max_prod = T::one();
                 let mut last_max: usize = 0;
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     if buf[j] == buf[i] {
                         nb_max += 1;
                         last_max = j;
@@ -209,7 +476,7 @@ pub(crate) fn amc_backprop_order<T: Semiring>(circuit: &Circuit, weights: &impl
                     }
                 }
                 grad_buf[last_max] = non_max_prod * &grad_buf[i] + &grad_buf[last_max];
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     if j != last_max {
                         grad_buf[j] = buf[i].clone() * &grad_buf[i] + &grad_buf[j];
                     }
@@ -218,40 +485,37 @@ pub(crate) fn amc_backprop_order<T: Semiring>(circuit: &Circuit, weights: &impl
             Node::Leaf(lit) => {
                 let lit_ix = (lit.abs() - 1) as usize;
                 if lit_ix < grad_weights.len() {
-                    grad_weights.add(*lit, &grad_buf[i]);
+                    grad_weights.add(lit, &grad_buf[i]);
                 }
             },
         }
     }
-    let duration = t1.elapsed().as_secs_f64();
-    (buf.last().unwrap().clone(), duration)
+    t1.elapsed().as_secs_f64()
 }
 
-pub(crate) fn amc_backprop_cancel<T: Field>(circuit: &Circuit, weights: &impl Weights<T>, grad_weights: &mut impl Weights<T>) -> (T, f64)
+pub(crate) fn backprop_cancel<T: Field>(circuit: &Circuit, buf: &[T], grad_root: T, grad_weights: &mut impl Weights<T>) -> f64
 {
-    // First compute the forward pass
-    let buf: Vec<T> = amc::<T>(circuit, weights);
     let t1 = Instant::now();
     // Buffer to store gradients of intermediate nodes
     let mut grad_buf: Vec<T> = vec![T::zero(); circuit.nb_nodes()];
     // Initialize the gradient of the output node
     let buf_size = grad_buf.len();
-    grad_buf[buf_size-1] = T::one();
+    grad_buf[buf_size-1] = grad_root;
 
     // Backpropagation
-    for (i, node) in circuit.nodes.iter().enumerate().rev() {
+    for (i, node) in circuit.nodes().enumerate().rev() {
         match node {
             Node::Or(children) => {
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     grad_buf[j] = grad_buf[j].clone() + &grad_buf[i];
                 }
             },
             Node::And(children) => {
-                for &j in children {
+                for j in children.iter().map(|&j| j as usize) {
                     let residual = if buf[i].has_inverse() {
                         buf[i].clone() / &buf[j]
                     } else {
-                        children.iter().flat_map(|&k| if k != j { Some(&buf[k]) } else { None }).product()
+                        children.iter().flat_map(|&k| if k as usize != j { Some(&buf[k as usize]) } else { None }).product()
                     };
                     grad_buf[j] = (residual * &grad_buf[i]) + &grad_buf[j];
                 }
@@ -259,12 +523,82 @@ pub(crate) fn amc_backprop_cancel<T: Field>(circuit: &Circuit, weights: &impl We
             Node::Leaf(lit) => {
                 let lit_ix = (lit.abs() - 1) as usize;
                 if lit_ix < grad_weights.len() {
-                    grad_weights.add(*lit, &grad_buf[i]);
+                    grad_weights.add(lit, &grad_buf[i]);
                 }
             },
         }
     }
-    let duration = t1.elapsed().as_secs_f64();
-    (buf.last().unwrap().clone(), duration)
+    t1.elapsed().as_secs_f64()
 }
 
+
+#[cfg(test)]
+mod tests {
+    use numpy::ndarray::array;
+    use crate::algebra::NdarraySemiring;
+    use crate::circuit::circuit::NodeKind;
+    use crate::circuit::weights::PosNegWeights;
+    use super::*;
+
+    #[test]
+    fn batched_gradients_with_a_zero_lane() {
+        // x1 ∧ x2, where x1 is zero in the first lane only.
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+        circuit.push(NodeKind::And, &[x1, x2]).unwrap();
+        let weights: PosNegWeights<NdarraySemiring> = PosNegWeights::from_vecs(
+            vec![array![0., 2.], array![3., 4.]],
+            vec![array![1., 1.], array![1., 1.]],
+        );
+        for algorithm in [GradAlgorithm::Auto, GradAlgorithm::Cancel, GradAlgorithm::CancelOrder, GradAlgorithm::Order] {
+            let mut grad_weights: PosNegWeights<NdarraySemiring> = PosNegWeights::new(2);
+            let (result, _) = amc_backprop(algorithm.batched(), &circuit, &weights, &mut grad_weights);
+            assert_eq!(result.0, array![0., 8.]);
+            let (pos_grads, _): (Vec<NdarraySemiring>, Vec<NdarraySemiring>) = grad_weights.into_vecs();
+            assert_eq!(pos_grads[0].0, array![3., 4.]);
+            assert_eq!(pos_grads[1].0, array![0., 2.]);
+        }
+    }
+
+    /// (x1 ∧ x2) ∨ (¬x1 ∧ x3)
+    fn circuit() -> Circuit {
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+        let not_x1 = circuit.push(NodeKind::Leaf(-1), &[]).unwrap();
+        let x3 = circuit.push(NodeKind::Leaf(3), &[]).unwrap();
+        let a = circuit.push(NodeKind::And, &[x1, x2]).unwrap();
+        let b = circuit.push(NodeKind::And, &[not_x1, x3]).unwrap();
+        circuit.push(NodeKind::Or, &[a, b]).unwrap();
+        circuit
+    }
+
+    #[test]
+    fn incremental_updates_match_a_full_evaluation() {
+        let circuit = circuit();
+        let mut weights: PosNegWeights<f64> = PosNegWeights::from_vecs(vec![0.2, 0.5, 0.7], vec![0.8, 0.5, 0.3]);
+        let mut buf = amc::<f64>(&circuit, &weights);
+        weights.set(-1, 0.4);
+        assert_eq!(amc_update(&circuit, &circuit.parents(), &weights, &mut buf, [2]), 0.2 * 0.5 + 0.4 * 0.7);
+        assert_eq!(buf, amc::<f64>(&circuit, &weights));
+        // Nodes whose value doesn't change stop the propagation.
+        buf[6] = 0.;
+        weights.set(2, 0.5);
+        assert_eq!(amc_update(&circuit, &circuit.parents(), &weights, &mut buf, [1]), 0.);
+    }
+
+
+    #[test]
+    fn parallel_evaluation_matches_sequential() {
+        let circuit = circuit();
+        let levels = [vec![0, 1, 2, 3], vec![4, 5], vec![6]];
+        let weights: PosNegWeights<f64> = PosNegWeights::from_vecs(vec![0.2, 0.5, 0.7], vec![0.8, 0.5, 0.3]);
+        assert_eq!(amc_par::<f64, _>(&circuit, &levels, &weights), amc::<f64>(&circuit, &weights));
+        let mut grads: PosNegWeights<f64> = PosNegWeights::new(3);
+        let mut grads_par: PosNegWeights<f64> = PosNegWeights::new(3);
+        amc_backprop(GradAlgorithm::Auto, &circuit, &weights, &mut grads);
+        amc_backprop_par(GradAlgorithm::Auto, &circuit, &levels, &weights, &mut grads_par);
+        assert_eq!(grads.into_vecs::<f64>(), grads_par.into_vecs::<f64>());
+    }
+}
@@ -1,163 +1,1188 @@
 use std::cell::RefCell;
+use std::fmt::{Debug, Display};
 use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 use num_traits::Float;
-use pyo3::{pyclass, pymethods, Python};
-use numpy::{Element, PyArray1, PyArray2, PyReadonlyArrayDyn, ToPyArray};
-use numpy::ndarray::{Array1, Array2, Axis, stack};
+use pyo3::{pyclass, pymethods, FromPyObject, IntoPy, PyAny, PyObject, PyRef, PyResult, Python};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::types::{IntoPyDict, PyBytes, PyLong};
+use numpy::{Element, PyArray1, PyArray2, PyArrayDescr, PyReadonlyArray1, PyReadonlyArrayDyn, ToPyArray};
+use numpy::ndarray::{Array1, Array2, ArrayViewD};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use crate::algebra::*;
-use crate::circuit::amc::{amc1, amc_backprop_alg1, amc_backprop_cancel, amc_backprop_cancel_order, amc_backprop_naive, amc_backprop_order};
-use crate::circuit::weights::{PosNegWeights, PosOnlyWeights};
+use crate::circuit::incremental::IncrementalEvaluator;
+use crate::circuit::models::{ModelIterator, ModelSearch};
+use crate::circuit::rcircuit::RCircuit;
+use crate::circuit::amc::{amc, amc1, amc_backprop, amc_backprop_par, amc_mpe, amc_par, amc_sample, backprop, backprop_par, GradAlgorithm};
+use crate::circuit::tape::{Tape, TapeBuffer};
+use crate::circuit::transform::{condition, forget, minimize, smooth};
+use crate::circuit::validate::{validate, ValidationReport};
+use crate::circuit::weights::{EvidenceWeights, PosNegWeights, PosOnlyWeights, Weights};
+use crate::error::{check_nan, Error, Result};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Node {
-    Or(Vec<usize>),
-    And(Vec<usize>),
+/// The kind of a node. The children of Or and And nodes are stored separately in the circuit.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum NodeKind {
+    Or,
+    And,
     Leaf(i32),
 }
 
+/// A node together with its children.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Node<'a> {
+    Or(&'a [u32]),
+    And(&'a [u32]),
+    Leaf(i32),
+}
 
-impl Node {
-    pub fn add_child(&mut self, child: usize) {
-        match self {
-            Node::Or(children) => children.push(child),
-            Node::And(children) => children.push(child),
-            _ => panic!("Can only add child to Or/And node"),
-        }
-    }
+
+/// Evidence as a list of literals, or as an integer or boolean matrix of shape (nb_vars, batch_size)
+/// with positive or negative values for observed values and 0 for unobserved variables.
+#[derive(FromPyObject)]
+enum Evidence<'py> {
+    Lits(Vec<i32>),
+    Batch(&'py PyAny),
 }
 
 
+// Gradients or marginals of the positive and negative literals, one array per variable.
+type LiteralArrays<F = f64> = (Vec<Array1<F>>, Vec<Array1<F>>);
+
+// Signs and log-magnitudes of batched results in the signed log domain.
+type SignedLogArrays<'py, S = PyArray2<bool>, L = PyArray2<f32>> = (&'py S, &'py L);
+type SignedLogGrad<'py> = (SignedLogArrays<'py, PyArray1<bool>, PyArray1<f32>>, SignedLogArrays<'py>, SignedLogArrays<'py>);
+// The result and the gradients of the positive and negative literals over dual numbers.
+type DualGrad = (DualSemiring<f64>, Vec<DualSemiring<f64>>, Vec<DualSemiring<f64>>);
+
+/// Nodes are referred to by u32 indices.
+pub(crate) const MAX_NODES: usize = u32::MAX as usize;
+
+/// Circuit in compressed sparse row layout, with the nodes topologically sorted and the root last.
+/// The children of node i are children[offsets[i]..offsets[i+1]].
 #[pyclass]
+#[derive(Debug)]
 pub struct Circuit {
-    pub nodes: Vec<Node>, // Assumes nodes are topologically sorted
+    kinds: Vec<NodeKind>,
+    offsets: Vec<usize>,
+    children: Vec<u32>,
+    pool: Option<Arc<ThreadPool>>, // Evaluates level-parallel when set, shared with derived circuits
+    levels: OnceLock<Vec<Vec<usize>>>, // Computed on the first parallel pass, cleared by push
+}
+
+impl PartialEq for Circuit {
+    fn eq(&self, other: &Self) -> bool {
+        self.kinds == other.kinds && self.offsets == other.offsets && self.children == other.children
+    }
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Circuit::new()
+    }
 }
 
 impl Circuit {
+    pub fn new() -> Self {
+        Circuit { kinds: vec![], offsets: vec![0], children: vec![], pool: None, levels: OnceLock::new() }
+    }
+
+    /// Appends a node, whose children must already be in the circuit, and returns its index.
+    /// Fails when a child isn't in the circuit yet, so that the nodes stay in topological order,
+    /// or when the index of the node doesn't fit into the u32 indices of the layout.
+    pub fn push(&mut self, kind: NodeKind, children: &[usize]) -> Result<usize> {
+        if let Some(j) = children.iter().find(|&&j| j >= self.kinds.len()) {
+            return Err(Error::InvalidCircuit(format!("Child {} of node {} isn't in the circuit yet", j, self.kinds.len())));
+        }
+        if self.kinds.len() >= MAX_NODES {
+            return Err(Error::InvalidCircuit(format!("Circuits have at most {} nodes", MAX_NODES)));
+        }
+        self.kinds.push(kind);
+        // Children come before the node, so their indices fit into u32.
+        self.children.extend(children.iter().map(|&j| j as u32));
+        self.offsets.push(self.children.len());
+        self.levels.take();
+        Ok(self.kinds.len() - 1)
+    }
+
+    /// Evaluates this circuit, derived from the other one, with the thread pool of the other one.
+    fn with_pool_of(mut self, other: &Circuit) -> Self {
+        self.pool = other.pool.clone();
+        self
+    }
+
+    pub fn node(&self, i: usize) -> Node<'_> {
+        match self.kinds[i] {
+            NodeKind::Or => Node::Or(&self.children[self.offsets[i]..self.offsets[i + 1]]),
+            NodeKind::And => Node::And(&self.children[self.offsets[i]..self.offsets[i + 1]]),
+            NodeKind::Leaf(lit) => Node::Leaf(lit),
+        }
+    }
+
+    pub fn nodes(&self) -> impl DoubleEndedIterator<Item = Node<'_>> + ExactSizeIterator {
+        (0..self.kinds.len()).map(|i| self.node(i))
+    }
+
+    /// Forward pass, which is level-parallel when the circuit has a thread pool.
+    fn _forward<T, W>(&self, weights: &W) -> Vec<T>
+        where T: Semiring + Send + Sync, W: Weights<T> + Sync
+    {
+        match &self.pool {
+            Some(pool) => pool.install(|| amc_par::<T, W>(self, self.levels(), weights)),
+            None => amc::<T>(self, weights),
+        }
+    }
+
+    fn _forward1<T, W>(&self, weights: &W) -> T
+        where T: Semiring + Send + Sync, W: Weights<T> + Sync
+    {
+        match &self.pool {
+            Some(_) => self._forward::<T, W>(weights).pop().unwrap(),
+            None => amc1::<T>(self, weights),
+        }
+    }
+
+    /// Backward pass, which is level-parallel when the circuit has a thread pool.
+    pub(crate) fn _backprop<T, G>(&self, algorithm: GradAlgorithm, buf: &[T], grad_root: T, grad_weights: &mut G) -> f64
+        where T: Field + Send + Sync, G: Weights<T> + Send
+    {
+        match &self.pool {
+            Some(pool) => pool.install(|| backprop_par(algorithm, self, self.levels(), buf, grad_root, grad_weights)),
+            None => backprop(algorithm, self, buf, grad_root, grad_weights),
+        }
+    }
+
+    fn _amc_backprop<T, W, G>(&self, algorithm: GradAlgorithm, weights: &W, grad_weights: &mut G) -> (T, f64)
+        where T: Field + Send + Sync, W: Weights<T> + Sync, G: Weights<T> + Send
+    {
+        match &self.pool {
+            Some(pool) => pool.install(|| amc_backprop_par::<T, W>(algorithm, self, self.levels(), weights, grad_weights)),
+            None => amc_backprop::<T>(algorithm, self, weights, grad_weights),
+        }
+    }
+
     fn _amc<W, T>(&self, weights: Vec<W>) -> T
-        where T: Ring + From<W>
+        where T: Ring + From<W> + Send + Sync
     {
         let weights: PosOnlyWeights<T> = PosOnlyWeights::from_vec(weights);
-        amc1::<T>(&self, &weights)
+        self._forward1(&weights)
+    }
+
+    /// Number of models over the variables of the circuit. Smoothing makes the variables
+    /// which are free below an Or node count twice, as all leaves have weight one.
+    /// Smooth circuit whose root mentions at least the variables 1 to `nb_vars`, evaluated
+    /// with the thread pool of this circuit.
+    fn _smooth(&self, nb_vars: usize) -> Result<Circuit> {
+        let nb_vars = nb_vars.max(self.nb_vars() as usize);
+        Ok(smooth(self, Some(nb_vars as u32))?.with_pool_of(self))
+    }
+
+    fn _mc<T>(&self) -> Result<T>
+        where T: Ring + Send + Sync
+    {
+        Ok(smooth(self, Some(self.nb_vars()))?.with_pool_of(self)._forward1(&PosOnlyWeights::<T>::new(0)))
     }
 
     fn _amc2<W, T>(&self, pos_weights: Vec<W>, neg_weights: Vec<W>) -> T
-        where T: Ring + From<W>
+        where T: Ring + From<W> + Send + Sync
     {
         let weights: PosNegWeights<T> = PosNegWeights::from_vecs(pos_weights, neg_weights);
-        amc1::<T>(&self, &weights)
+        self._forward1(&weights)
     }
 
-    fn _amc_grad<W, T>(&self, weights: Vec<W>) -> (W, Vec<W>, f64)
-        where W: From<T>, T: Field + From<W>
+    fn _amc_grad<W, T>(&self, weights: Vec<W>, algorithm: GradAlgorithm) -> (W, Vec<W>, f64)
+        where W: From<T>, T: Field + From<W> + Send + Sync
     {
         let mut grad_weights = PosOnlyWeights::new(weights.len());
         let weights = PosOnlyWeights::from_vec(weights);
-        let (result, duration) = amc_backprop_cancel_order::<T>(&self, &weights, &mut grad_weights);
+        let (result, duration) = self._amc_backprop::<T, _, _>(algorithm, &weights, &mut grad_weights);
         (result.into(), grad_weights.into_vec(), duration)
     }
 
-    fn _amc_grad2<W, T>(&self, pos_weights: Vec<W>, neg_weights: Vec<W>) -> (W, Vec<W>, Vec<W>, f64)
-        where W: From<T>, T: Field + From<W>
+    fn _amc_grad2<W, T>(&self, pos_weights: Vec<W>, neg_weights: Vec<W>, algorithm: GradAlgorithm) -> (W, Vec<W>, Vec<W>, f64)
+        where W: From<T>, T: Field + From<W> + Send + Sync
     {
         let mut grad_weights = PosNegWeights::new(pos_weights.len());
         let weights = PosNegWeights::from_vecs(pos_weights, neg_weights);
-        let (result, duration) = amc_backprop_cancel_order::<T>(&self, &weights, &mut grad_weights);
+        let (result, duration) = self._amc_backprop::<T, _, _>(algorithm, &weights, &mut grad_weights);
         let (pos_grad_weights, neg_grad_weights) = grad_weights.into_vecs();
         (result.into(), pos_grad_weights, neg_grad_weights, duration)
     }
 
+    fn _amc_np_grad2<W, T>(&self, pos_weights: Vec<W>, neg_weights: Vec<W>, algorithm: GradAlgorithm) -> (W, Vec<W>, Vec<W>)
+        where W: From<T>, T: Field + From<W> + Send + Sync
+    {
+        let mut grad_weights = PosNegWeights::new(pos_weights.len());
+        let weights = PosNegWeights::from_vecs(pos_weights, neg_weights);
+        let (result, _) = self._amc_backprop::<T, _, _>(algorithm.batched(), &weights, &mut grad_weights);
+        let (pos_grad_weights, neg_grad_weights) = grad_weights.into_vecs();
+        (result.into(), pos_grad_weights, neg_grad_weights)
+    }
+
+    fn _mpe<W, T>(&self, pos_weights: Vec<W>, neg_weights: Vec<W>) -> Result<(W, Vec<bool>)>
+        where W: From<T>, T: Semiring + From<W>
+    {
+        let nb_vars = pos_weights.len().max(self.nb_vars() as usize);
+        let weights: PosNegWeights<T> = PosNegWeights::from_vecs(pos_weights, neg_weights);
+        // In a smooth circuit whose root mentions every variable, the maximum of each Or node is
+        // over complete assignments of its scope, and the MPE branch assigns every variable.
+        let smoothed = smooth(self, Some(nb_vars as u32))?;
+        let (result, assignment) = amc_mpe::<T>(&smoothed, &weights, nb_vars);
+        Ok((result.into(), assignment.into_iter().map(|value| value.unwrap_or(false)).collect()))
+    }
+
+    fn _top_k(&self, mut pos_weights: Vec<f64>, mut neg_weights: Vec<f64>, k: usize) -> Result<Vec<(f64, Vec<bool>)>> {
+        if k == 0 {
+            return Ok(vec![]);
+        }
+        let nb_vars = pos_weights.len().max(self.nb_vars() as usize);
+        pos_weights.resize(nb_vars, 1.);
+        neg_weights.resize(nb_vars, 1.);
+        let pos_lits: Vec<KBestSemiring> = (1..=nb_vars).map(|v| KBestSemiring::leaf(k, pos_weights[v - 1], v as i32)).collect();
+        let neg_lits: Vec<KBestSemiring> = (1..=nb_vars).map(|v| KBestSemiring::leaf(k, neg_weights[v - 1], -(v as i32))).collect();
+        let weights: PosNegWeights<KBestSemiring> = PosNegWeights::from_vecs(pos_lits, neg_lits);
+        // Partial solutions must not be ranked, so every Or node of the smoothed circuit
+        // only truncates solutions which are complete for its scope.
+        let result = amc1::<KBestSemiring>(&smooth(self, Some(nb_vars as u32))?, &weights);
+
+        Ok(result.solutions.into_iter().map(|(score, lits)| {
+            let mut assignment = vec![false; nb_vars];
+            lits.iter().for_each(|lit| assignment[(lit.abs() - 1) as usize] = *lit > 0);
+            (score, assignment)
+        }).collect())
+    }
+
+    /// Returns <Z, sum_x w(x) f(x)> for an additive feature f, where x ranges over the
+    /// assignments of every weighted variable. The circuit is smoothed first, as variables
+    /// which are free or absent would not contribute their feature values otherwise.
+    fn _expectation<T: Semiring>(&self, pos_weights: Vec<T>, neg_weights: Vec<T>, pos_features: Vec<T>, neg_features: Vec<T>) -> Result<ExpectationSemiring<T>> {
+        let nb_vars = pos_weights.len().max(self.nb_vars() as usize) as u32;
+        let weights: PosNegWeights<ExpectationSemiring<T>> = PosNegWeights::from_vecs(
+            pos_weights.into_iter().zip(&pos_features).map(|(w, f)| ExpectationSemiring::leaf(w, f)).collect(),
+            neg_weights.into_iter().zip(&neg_features).map(|(w, f)| ExpectationSemiring::leaf(w, f)).collect(),
+        );
+        Ok(amc1::<ExpectationSemiring<T>>(&smooth(self, Some(nb_vars))?, &weights))
+    }
+
+    /// Returns <Z, sum_x w(x) f(x), sum_x w(x) g(x), sum_x w(x) f(x) g(x)> for additive features f and g,
+    /// also on the smoothed circuit.
+    fn _second_order_expectation<T: Semiring>(&self, pos_weights: Vec<T>, neg_weights: Vec<T>, pos_f: Vec<T>, neg_f: Vec<T>, pos_g: Vec<T>, neg_g: Vec<T>) -> Result<SecondOrderExpectationSemiring<T>> {
+        let nb_vars = pos_weights.len().max(self.nb_vars() as usize) as u32;
+        let weights: PosNegWeights<SecondOrderExpectationSemiring<T>> = PosNegWeights::from_vecs(
+            pos_weights.into_iter().zip(pos_f.iter().zip(&pos_g)).map(|(w, (f, g))| SecondOrderExpectationSemiring::leaf(w, f, g)).collect(),
+            neg_weights.into_iter().zip(neg_f.iter().zip(&neg_g)).map(|(w, (f, g))| SecondOrderExpectationSemiring::leaf(w, f, g)).collect(),
+        );
+        Ok(amc1::<SecondOrderExpectationSemiring<T>>(&smooth(self, Some(nb_vars))?, &weights))
+    }
+
+    /// Log-marginals of all literals from the node values of a forward pass,
+    /// normalising the gradient by the partition function in the log domain.
+    fn _log_marginals<F>(&self, buf: &[LogNdarraySemiring<F>], pos_weights: &[Array1<F>], neg_weights: &[Array1<F>], batch_size: usize, algorithm: GradAlgorithm) -> Result<LiteralArrays<F>>
+        where F: Float + Display + Debug + Send + Sync
+    {
+        let mut grad_weights = PosNegWeights::new(pos_weights.len());
+        self._backprop(algorithm.batched(), buf, LogNdarraySemiring::one(), &mut grad_weights);
+        let (pos_grads, neg_grads) = grad_weights.into_vecs();
+        // log P(l) = log w_l + log dZ/dw_l - log Z
+        let log_z = broadcast(buf.last().unwrap().0.clone(), batch_size);
+        let normalise = |grads: Vec<Array1<F>>, weights: &[Array1<F>]| -> Vec<Array1<F>> {
+            grads.into_iter().zip(weights)
+                .map(|(g, w)| broadcast(g, batch_size) + w - &log_z)
+                .collect()
+        };
+        let (pos_marginals, neg_marginals) = (normalise(pos_grads, pos_weights), normalise(neg_grads, neg_weights));
+        check_nan(pos_marginals.iter().chain(&neg_marginals).flatten(), "log-marginal")?;
+        Ok((pos_marginals, neg_marginals))
+    }
+
+    /// Log-marginals of all literals, on the smoothed circuit so that the variables which are
+    /// free below an Or node are counted.
+    fn _log_marginals_np(&self, pos_weights: Vec<Array1<f64>>, neg_weights: Vec<Array1<f64>>, batch_size: usize, algorithm: GradAlgorithm) -> Result<LiteralArrays> {
+        let smoothed = self._smooth(pos_weights.len())?;
+        let weights: PosNegWeights<LogNdarraySemiring<f64>> = PosNegWeights::from_vecs(pos_weights.clone(), neg_weights.clone());
+        let buf = smoothed._forward(&weights);
+        smoothed._log_marginals(&buf, &pos_weights, &neg_weights, batch_size, algorithm)
+    }
+
+    /// Forward-over-reverse differentiation: backpropagation over dual numbers
+    /// gives the gradient together with the Hessian-vector product, on the smoothed circuit.
+    /// Returns <Z, g·v> and the <g, Hv> pairs of the positive and negative literals.
+    fn _hvp(&self, pos_weights: Vec<f64>, neg_weights: Vec<f64>, pos_vector: Vec<f64>, neg_vector: Vec<f64>, algorithm: GradAlgorithm) -> Result<DualGrad> {
+        Ok(self._smooth(pos_weights.len())?._dual_backprop(pos_weights, neg_weights, pos_vector, neg_vector, algorithm))
+    }
+
+    /// See `_hvp`, on this circuit as it is.
+    fn _dual_backprop(&self, pos_weights: Vec<f64>, neg_weights: Vec<f64>, pos_vector: Vec<f64>, neg_vector: Vec<f64>, algorithm: GradAlgorithm) -> DualGrad {
+        let mut grad_weights = PosNegWeights::new(pos_weights.len());
+        let weights: PosNegWeights<DualSemiring<f64>> = PosNegWeights::from_vecs(
+            pos_weights.into_iter().zip(pos_vector).collect(),
+            neg_weights.into_iter().zip(neg_vector).collect(),
+        );
+        let (result, _) = self._amc_backprop(algorithm, &weights, &mut grad_weights);
+        let (pos_grads, neg_grads) = grad_weights.into_vecs();
+        (result, pos_grads, neg_grads)
+    }
+
+    /// See `pairwise_marginals`, the variables must be between 1 and the number of weights.
+    fn _pairwise_marginals(&self, pos_weights: &[f64], neg_weights: &[f64], vars: &[usize]) -> Result<Vec<Vec<f64>>> {
+        let nb_vars = pos_weights.len();
+        let smoothed = self._smooth(nb_vars)?;
+        Ok(vars.iter().map(|&j| {
+            let mut pos_vector = vec![0.; nb_vars];
+            pos_vector[j - 1] = 1.;
+            let (z, pos_grads, _) = smoothed._dual_backprop(pos_weights.to_vec(), neg_weights.to_vec(), pos_vector, vec![0.; nb_vars], GradAlgorithm::Auto);
+            vars.iter().map(|&i| {
+                // The WMC is multilinear, so w_i w_j ∂²Z/∂w_i∂w_j sums the models with x_i and x_j.
+                if i == j {
+                    pos_weights[i - 1] * pos_grads[i - 1].0 / z.0
+                } else {
+                    pos_weights[i - 1] * pos_weights[j - 1] * pos_grads[i - 1].1 / z.0
+                }
+            }).collect()
+        }).collect())
+    }
+
+    /// P(query | evidence) for a list of evidence literals, see `query`.
+    fn _query(&self, weights: Vec<f64>, evidence: &[i32], query_lits: &[i32]) -> Result<f64> {
+        let nb_vars = evidence.iter().chain(query_lits).map(|lit| lit.unsigned_abs() as usize).max().unwrap_or(0).max(weights.len());
+        let smoothed = self._smooth(nb_vars)?;
+        let mut weights = EvidenceWeights::new(PosOnlyWeights::<f64>::from_vec(weights));
+        weights.observe(evidence);
+        let p_evidence = smoothed._forward1(&weights);
+        if p_evidence == 0. {
+            return Err(Error::Numerical("The evidence has probability zero".to_string()));
+        }
+        weights.observe(query_lits);
+        Ok(smoothed._forward1(&weights) / p_evidence)
+    }
+
+    /// P(query | evidence) for evidence of shape (nb_vars, batch_size), where positive values
+    /// observe the variable as true and negative ones as false, see `query`.
+    fn _query_batch(&self, weights: &[f64], evidence: ArrayViewD<i64>, query_lits: &[i32]) -> Result<Array1<f64>> {
+        let batch_size = evidence.shape()[1];
+        // Literals without weight have weight one, like in PosOnlyWeights.
+        let nb_vars = weights.len().max(evidence.shape()[0]);
+        let smoothed = self._smooth(query_lits.iter().map(|lit| lit.unsigned_abs() as usize).max().unwrap_or(0).max(nb_vars))?;
+        let mut pos_weights: Vec<Array1<f64>> = (0..nb_vars).map(|i| Array1::from_elem(batch_size, weights.get(i).copied().unwrap_or(1.))).collect();
+        let mut neg_weights: Vec<Array1<f64>> = (0..nb_vars).map(|i| Array1::from_elem(batch_size, weights.get(i).map_or(1., |w| 1. - w))).collect();
+        for (var_ix, values) in evidence.rows().into_iter().enumerate() {
+            for (lane, &value) in values.iter().enumerate() {
+                if value > 0 {
+                    neg_weights[var_ix][lane] = 0.;
+                } else if value < 0 {
+                    pos_weights[var_ix][lane] = 0.;
+                }
+            }
+        }
+        let mut weights = EvidenceWeights::new(PosNegWeights::<NdarraySemiring>::from_vecs(pos_weights, neg_weights));
+        let p_evidence = broadcast(smoothed._forward1(&weights).0, batch_size);
+        let zero_lanes: Vec<usize> = p_evidence.iter().enumerate().filter(|(_, &p)| p == 0.).map(|(lane, _)| lane).collect();
+        if !zero_lanes.is_empty() {
+            return Err(Error::Numerical(format!("The evidence has probability zero for batch elements {:?}", zero_lanes)));
+        }
+        weights.observe(query_lits);
+        let p_query = broadcast(smoothed._forward1(&weights).0, batch_size);
+        Ok(p_query / p_evidence)
+    }
+
+    /// Draws samples from the forward buffer, see `sample`.
+    fn _sample(&self, weights: Vec<f64>, batch_size: usize, evidence: Option<Vec<i32>>, seed: Option<u64>) -> Result<Vec<Vec<bool>>> {
+        let evidence = evidence.unwrap_or_default();
+        let nb_vars = evidence.iter().map(|lit| lit.unsigned_abs() as usize).max().unwrap_or(0).max(weights.len()).max(self.nb_vars() as usize);
+        // In a smooth circuit whose root mentions every variable, the sampled branch assigns
+        // every variable, with the probabilities conditioned on the evidence.
+        let smoothed = self._smooth(nb_vars)?;
+        let mut weights = EvidenceWeights::new(PosOnlyWeights::<f64>::from_vec(weights));
+        weights.observe(&evidence);
+        let buf = smoothed._forward(&weights);
+        if *buf.last().unwrap() == 0. {
+            return Err(Error::Numerical("The evidence has probability zero".to_string()));
+        }
+        let mut rng = seeded_rng(seed);
+        Ok((0..batch_size).map(|_| {
+            amc_sample(&smoothed, &buf, nb_vars, &mut rng).into_iter()
+                .map(|value| value.unwrap_or(false))
+                .collect()
+        }).collect())
+    }
+
+    /// Vector-Jacobian product of a tape, see `backward`.
+    fn _backward(&self, tape: &Tape, upstream_grad: Array1<f64>, algorithm: GradAlgorithm) -> Result<LiteralArrays> {
+        Ok(match &tape.buf {
+            TapeBuffer::Real(buf) => {
+                check_tape(self, buf)?;
+                let mut grad_weights = PosNegWeights::new(tape.nb_vars);
+                self._backprop(algorithm, buf, upstream_grad.into(), &mut grad_weights);
+                grad_weights.into_vecs()
+            },
+            TapeBuffer::Log(buf, pos_weights, neg_weights) => {
+                check_tape(self, buf)?;
+                // d log Z / d log w = w (dZ/dw) / Z, i.e. the marginal of the literal
+                let (pos_marginals, neg_marginals) = self._log_marginals(buf, pos_weights, neg_weights, tape.batch_size, algorithm)?;
+                let vjp = |log_marginals: Vec<Array1<f32>>| -> Vec<Array1<f64>> {
+                    log_marginals.into_iter()
+                        .map(|m| m.mapv(|x| (x as f64).exp()) * &upstream_grad)
+                        .collect()
+                };
+                (vjp(pos_marginals), vjp(neg_marginals))
+            },
+        })
+    }
+
+    /// Evaluates the circuit with a symbol for every literal weight.
+    fn _symbolic<T: Semiring + From<i32>>(&self) -> T {
+        let nb_vars = self.nb_vars() as i32;
+        let weights: PosNegWeights<T> = PosNegWeights::from_vecs((1..=nb_vars).collect(), (1..=nb_vars).map(|v| -v).collect());
+        amc1::<T>(self, &weights)
+    }
+
+    /// Whether some model of the circuit makes all the literals true.
+    fn _consistent_with(&self, lits: &[i32]) -> bool {
+        let nb_vars = lits.iter().map(|lit| lit.unsigned_abs()).max().unwrap_or(0).max(self.nb_vars());
+        let mut weights: PosNegWeights<BoolSemiring> = PosNegWeights::from_vecs(vec![true; nb_vars as usize], vec![true; nb_vars as usize]);
+        lits.iter().for_each(|&lit| weights.set(-lit, false.into()));
+        self._forward1(&weights).into()
+    }
+
+    /// Exact probability of the circuit when the literals are true, and the other variables
+    /// are true or false with probability one half.
+    fn _probability_given(&self, lits: &[i32]) -> BigRational {
+        let nb_vars = lits.iter().map(|lit| lit.unsigned_abs()).max().unwrap_or(0).max(self.nb_vars());
+        let half = BigRational::new(1.into(), 2.into());
+        let mut weights: PosNegWeights<BigRational> = PosNegWeights::from_vecs(vec![half.clone(); nb_vars as usize], vec![half; nb_vars as usize]);
+        for &lit in lits {
+            weights.set(lit, BigRational::one());
+            weights.set(-lit, BigRational::zero());
+        }
+        self._forward1(&weights)
+    }
+
+    /// Compares the log-WMC of both circuits for random weights, see `is_equivalent`.
+    fn _is_equivalent<R: Rng>(&self, other: &Circuit, nb_trials: usize, tolerance: f64, rng: &mut R) -> bool {
+        // All trials in one batch, in the log domain as the WMC of many variables underflows.
+        let nb_vars = self.nb_vars().max(other.nb_vars());
+        let weights: Vec<Array1<f64>> = (0..nb_vars)
+            .map(|_| (0..nb_trials).map(|_| rng.gen_range(0.1f64..0.9).ln()).collect())
+            .collect();
+        let a: Array1<f64> = self._amc::<_, LogNdarraySemiring<f64>>(weights.clone()).into();
+        let b: Array1<f64> = other._amc::<_, LogNdarraySemiring<f64>>(weights).into();
+        broadcast(a, nb_trials).iter().zip(&broadcast(b, nb_trials))
+            .all(|(&a, &b)| a == b || (a - b).abs() <= tolerance * a.abs().min(b.abs()).max(1.))
+    }
+
+    /// Depth of every node, where leaves have depth one.
+    fn depths(&self) -> Vec<usize> {
+        let mut depths: Vec<usize> = vec![0; self.nb_nodes()];
+        for (i, node) in self.nodes().enumerate() {
+            depths[i] = match node {
+                Node::Or(children) | Node::And(children) => {
+                    children.iter().map(|&j| depths[j as usize]).max().unwrap_or(0) + 1
+                },
+                _ => 1,
+            };
+        }
+        depths
+    }
+
+    /// The nodes grouped by depth, such that every level only depends on the levels before it.
+    fn levels(&self) -> &[Vec<usize>] {
+        self.levels.get_or_init(|| {
+            let mut levels: Vec<Vec<usize>> = vec![];
+            for (i, depth) in self.depths().into_iter().enumerate() {
+                if depth > levels.len() {
+                    levels.resize(depth, vec![]);
+                }
+                levels[depth - 1].push(i);
+            }
+            levels
+        })
+    }
+
+    pub(crate) fn parents(&self) -> Vec<Vec<usize>> {
+        let mut parents = vec![vec![]; self.nb_nodes()];
+        for (i, node) in self.nodes().enumerate() {
+            if let Node::Or(children) | Node::And(children) = node {
+                for &j in children {
+                    parents[j as usize].push(i);
+                }
+            }
+        }
+        parents
+    }
+
+    /// Variables mentioned below every node, as sorted lists.
+    pub(crate) fn scopes(&self) -> Vec<Vec<u32>> {
+        let mut scopes: Vec<Vec<u32>> = Vec::with_capacity(self.nb_nodes());
+        for node in self.nodes() {
+            let scope = match node {
+                Node::Or(children) | Node::And(children) => children.iter()
+                    .fold(vec![], |scope, &j| sorted_union(&scope, scopes.get(j as usize).map_or(&[], |s| s))),
+                Node::Leaf(lit) => vec![lit.unsigned_abs()],
+            };
+            scopes.push(scope);
+        }
+        scopes
+    }
+
+}
+
+/// Union of two sorted lists.
+pub(crate) fn sorted_union(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut union = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            union.push(a[i]);
+            i += 1;
+        } else if a[i] > b[j] {
+            union.push(b[j]);
+            j += 1;
+        } else {
+            union.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    union.extend_from_slice(&a[i..]);
+    union.extend_from_slice(&b[j..]);
+    union
 }
 
 
 #[pymethods]
 impl Circuit {
+    /// Number of threads for level-parallel evaluation, where 0 uses all cores.
+    /// The circuits returned by smooth, minimize, condition and forget share the thread pool.
+    #[getter]
+    fn num_threads(&self) -> usize {
+        self.pool.as_ref().map_or(1, |pool| pool.current_num_threads())
+    }
+
+    #[setter]
+    fn set_num_threads(&mut self, num_threads: usize) -> PyResult<()> {
+        self.pool = match num_threads {
+            1 => None,
+            _ => Some(Arc::new(ThreadPoolBuilder::new().num_threads(num_threads).build().map_err(|e| PyValueError::new_err(e.to_string()))?)),
+        };
+        Ok(())
+    }
+
     pub fn nb_nodes(&self) -> usize {
-        self.nodes.len()
+        self.kinds.len()
+    }
+
+    pub fn nb_edges(&self) -> usize {
+        self.children.len()
     }
 
     pub fn nb_vars(&self) -> u32 {
-        self.nodes.iter().flat_map(|node| match node {
-            Node::Leaf(v) => Some(v.abs() as u32),
+        self.kinds.iter().flat_map(|kind| match kind {
+            NodeKind::Leaf(v) => Some(v.unsigned_abs()),
             _ => None,
         }).max().unwrap_or(0)
     }
 
-    fn _bool_grad(&self, pos_weights: Vec<bool>, neg_weights: Vec<bool>) -> (bool, Vec<bool>, Vec<bool>, f64) {
-        self._amc_grad2::<bool, BoolSemiring>(pos_weights, neg_weights)
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn _bool_grad(&self, py: Python, pos_weights: Vec<bool>, neg_weights: Vec<bool>, algorithm: &str) -> PyResult<(bool, Vec<bool>, Vec<bool>, f64)> {
+        let algorithm = parse_algorithm(algorithm)?;
+        Ok(py.allow_threads(|| self._amc_grad2::<bool, BoolSemiring>(pos_weights, neg_weights, algorithm)))
     }
 
 
-    fn mc(&self) -> i32 {
-        self._amc::<i32, i32>(vec![])
+    /// Number of models over the variables 1 to nb_vars() of the circuit.
+    fn mc(&self, py: Python) -> Result<i32> {
+        py.allow_threads(|| self._mc::<i32>())
     }
 
-    fn wmc(&self, weights: Vec<f64>) -> f64 {
-        self._amc::<f64, f64>(weights)
+    /// Exact model count, which can't overflow like mc().
+    fn mc_exact(&self, py: Python) -> PyResult<PyObject> {
+        bigint_to_py(py, &py.allow_threads(|| self._mc::<BigInt>())?)
     }
 
-    fn _wmc_grad(&self, weights: Vec<f64>) -> (f64, Vec<f64>, f64) {
-        self._amc_grad::<f64, f64>(weights)
+    fn wmc(&self, py: Python, weights: Vec<f64>) -> f64 {
+        py.allow_threads(|| self._amc::<f64, f64>(weights))
     }
 
-    fn _log_grad(&self, pos_weights: Vec<f32>, neg_weights: Vec<f32>) -> (f32, Vec<f32>, Vec<f32>, f64) {
-        self._amc_grad2::<f32, LogSemiring>(pos_weights, neg_weights)
+    /// P(query | evidence) for a conjunction of query literals, by clamping the weights
+    /// to the evidence. Batched evidence gives an array of probabilities.
+    fn query(&self, py: Python, evidence: Evidence, query_lits: Vec<i32>, weights: Vec<f64>) -> PyResult<PyObject> {
+        check_lits(&query_lits)?;
+        match evidence {
+            Evidence::Lits(evidence) => {
+                check_lits(&evidence)?;
+                Ok(py.allow_threads(|| self._query(weights, &evidence, &query_lits))?.into_py(py))
+            },
+            Evidence::Batch(evidence) => {
+                let evidence = evidence_array(evidence)?;
+                let evidence = evidence.as_array();
+                if evidence.ndim() != 2 {
+                    return Err(PyValueError::new_err("Batched evidence must have shape (nb_vars, batch_size)"));
+                }
+                let result = py.allow_threads(|| self._query_batch(&weights, evidence, &query_lits))?;
+                Ok(result.to_pyarray(py).into_py(py))
+            },
+        }
     }
 
-    fn _fuzzy_grad(&self, pos_weights: Vec<f32>, neg_weights: Vec<f32>) -> (f32, Vec<f32>, Vec<f32>, f64) {
-        self._amc_grad2::<f32, FuzzySemiring>(pos_weights, neg_weights)
+    /// Draws i.i.d. models from the distribution defined by the weights, optionally conditioned
+    /// on a list of evidence literals. A seed makes the samples reproducible.
+    #[pyo3(signature = (weights, batch_size = 1, evidence = None, seed = None))]
+    fn sample(&self, py: Python, weights: Vec<f64>, batch_size: usize, evidence: Option<Vec<i32>>, seed: Option<u64>) -> PyResult<Vec<Vec<bool>>> {
+        check_lits(evidence.as_deref().unwrap_or_default())?;
+        Ok(py.allow_threads(|| self._sample(weights, batch_size, evidence, seed))?)
+    }
+
+    /// Evaluates the WMC once, for later updates of a few weights at a time.
+    fn incremental(slf: PyRef<'_, Self>, pos_weights: Vec<f64>, neg_weights: Vec<f64>) -> PyResult<IncrementalEvaluator> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights)])?;
+        let weights = PosNegWeights::from_vecs(pos_weights, neg_weights);
+        let circuit: &Circuit = &slf;
+        let buf = slf.py().allow_threads(|| circuit._forward(&weights));
+        Ok(IncrementalEvaluator::new(slf, weights, buf))
+    }
+
+    /// Checks the reachability, decomposability, smoothness and determinism of the nodes.
+    /// Determinism is checked exactly up to `exact_vars` variables, and otherwise
+    /// on `nb_samples` assignments sampled from the circuit with random weights.
+    #[pyo3(signature = (exact_vars = 16, nb_samples = 1024, seed = None))]
+    fn validate(&self, py: Python, exact_vars: usize, nb_samples: usize, seed: Option<u64>) -> ValidationReport {
+        let mut rng = seeded_rng(seed);
+        py.allow_threads(|| validate(self, exact_vars, nb_samples, &mut rng))
+    }
+
+    /// Equivalent smooth circuit, where the children of every Or node mention the same variables.
+    /// Missing variables are added with `(x ∨ ¬x)` nodes, shared across the circuit. When `nb_vars`
+    /// is given, the circuit is also smoothed with respect to all variables from 1 to `nb_vars`.
+    #[pyo3(signature = (nb_vars = None))]
+    fn smooth(&self, py: Python, nb_vars: Option<u32>) -> PyResult<Circuit> {
+        if nb_vars.is_some_and(|nb_vars| nb_vars < self.nb_vars()) {
+            return Err(PyValueError::new_err(format!("The circuit has {} variables", self.nb_vars())));
+        }
+        Ok(py.allow_threads(|| smooth(self, nb_vars))?.with_pool_of(self))
+    }
+
+    /// Equivalent circuit with structurally equal nodes merged, constants folded, unary gates
+    /// collapsed and nodes unreachable from the root removed.
+    fn minimize(&self, py: Python) -> Result<Circuit> {
+        Ok(py.allow_threads(|| minimize(self))?.with_pool_of(self))
+    }
+
+    /// Circuit with the literals set to true and their negations to false, then minimized.
+    fn condition(&self, py: Python, lits: Vec<i32>) -> PyResult<Circuit> {
+        check_lits(&lits)?;
+        if let Some(lit) = lits.iter().find(|&&lit| lits.contains(&-lit)) {
+            return Err(PyValueError::new_err(format!("Both {} and {} are given", lit, -lit)));
+        }
+        Ok(py.allow_threads(|| condition(self, &lits))?.with_pool_of(self))
     }
 
-    fn circuit_transform(&self) -> Circuit {
+    /// Existentially quantifies the variables, and returns the minimized circuit together with
+    /// whether it is still deterministic. When it is not, the circuit remains a DNNF, which
+    /// still supports consistency and clausal entailment, but not (weighted) model counting,
+    /// `is_valid`, `is_implicant` or `is_equivalent`.
+    fn forget(&self, py: Python, vars: Vec<u32>) -> PyResult<(Circuit, bool)> {
+        if vars.contains(&0) {
+            return Err(PyValueError::new_err("Variables are numbered from 1"));
+        }
+        let (circuit, deterministic) = py.allow_threads(|| forget(self, &vars))?;
+        Ok((circuit.with_pool_of(self), deterministic))
+    }
+
+    /// Whether the circuit has a model (CO).
+    fn is_consistent(&self, py: Python) -> bool {
+        py.allow_threads(|| self._consistent_with(&[]))
+    }
+
+    /// Whether every assignment is a model of the circuit (VA). Assumes a d-DNNF, so the
+    /// answer may be wrong after `forget`, which only keeps decomposability.
+    fn is_valid(&self, py: Python) -> bool {
+        py.allow_threads(|| self._probability_given(&[]) == BigRational::one())
+    }
+
+    /// Whether the circuit entails the clause, given as a list of literals (CE).
+    fn entails(&self, py: Python, clause: Vec<i32>) -> PyResult<bool> {
+        check_lits(&clause)?;
+        let negation: Vec<i32> = clause.iter().map(|&lit| -lit).collect();
+        Ok(py.allow_threads(|| !self._consistent_with(&negation)))
+    }
+
+    /// Whether the term, given as a list of literals, entails the circuit (IM). Assumes a d-DNNF,
+    /// so the answer may be wrong after `forget`, which only keeps decomposability.
+    fn is_implicant(&self, py: Python, term: Vec<i32>) -> PyResult<bool> {
+        check_lits(&term)?;
+        if term.iter().any(|&lit| term.contains(&-lit)) {
+            return Ok(true);
+        }
+        Ok(py.allow_threads(|| self._probability_given(&term) == BigRational::one()))
+    }
+
+    /// Whether the assignment, with a value for every variable, is a model of the circuit (MC).
+    fn is_model(&self, py: Python, assignment: Vec<bool>) -> PyResult<bool> {
+        if assignment.len() < self.nb_vars() as usize {
+            return Err(PyValueError::new_err(format!("The assignment has {} variables but the circuit {}", assignment.len(), self.nb_vars())));
+        }
+        let lits: Vec<i32> = (1..).zip(assignment).map(|(v, value)| if value { v } else { -v }).collect();
+        Ok(py.allow_threads(|| self._consistent_with(&lits)))
+    }
+
+    /// Whether both circuits have the same models, by comparing their log-WMC for `nb_trials` random
+    /// weights. Assumes both are d-DNNFs, e.g. not the result of `forget`, and may only be wrong
+    /// when they are not equivalent.
+    /// Two log-WMCs are equal when they differ by at most `tolerance` relative to the smaller magnitude,
+    /// or absolutely below a magnitude of one, which absorbs the rounding errors of large circuits.
+    #[pyo3(signature = (other, nb_trials = 8, tolerance = 1e-9, seed = None))]
+    fn is_equivalent(&self, py: Python, other: PyRef<'_, Circuit>, nb_trials: usize, tolerance: f64, seed: Option<u64>) -> PyResult<bool> {
+        if nb_trials == 0 {
+            return Err(PyValueError::new_err("nb_trials must be positive"));
+        }
+        if tolerance.is_nan() || tolerance < 0. {
+            return Err(PyValueError::new_err(format!("tolerance must be non-negative, got {}", tolerance)));
+        }
+        let other: &Circuit = &other;
+        let mut rng = seeded_rng(seed);
+        Ok(py.allow_threads(|| self._is_equivalent(other, nb_trials, tolerance, &mut rng)))
+    }
+
+    /// Lazily enumerates the models as lists of literals, projected on the given variables
+    /// (all variables by default, repeated variables count once), and stops after at most `limit` models.
+    #[pyo3(signature = (variables = None, limit = None))]
+    fn models(slf: PyRef<'_, Self>, variables: Option<Vec<u32>>, limit: Option<usize>) -> PyResult<ModelIterator> {
+        let variables = variables.unwrap_or_else(|| (1..=slf.nb_vars()).collect());
+        if variables.contains(&0) {
+            return Err(PyValueError::new_err("Variables are numbered from 1"));
+        }
+        let circuit: &Circuit = &slf;
+        let search = slf.py().allow_threads(|| ModelSearch::new(circuit, variables));
+        Ok(ModelIterator::new(slf, search, limit))
+    }
+
+    /// Exact WMC with rational weights (int, float or fractions.Fraction), returned as a Fraction.
+    fn wmc_exact(&self, py: Python, weights: Vec<&PyAny>) -> PyResult<PyObject> {
+        let weights: Vec<BigRational> = weights.into_iter().map(to_rational).collect::<PyResult<_>>()?;
+        let result = py.allow_threads(|| self._amc::<BigRational, BigRational>(weights));
+        let fraction = py.import("fractions")?.getattr("Fraction")?;
+        Ok(fraction.call1((bigint_to_py(py, result.numer())?, bigint_to_py(py, result.denom())?))?.into())
+    }
+
+    /// WMC and its directional derivative along the tangent, computed in a single forward pass.
+    fn jvp(&self, py: Python, weights: Vec<f64>, tangent: Vec<f64>) -> PyResult<(f64, f64)> {
+        check_lengths(&[("weights", &weights), ("tangent", &tangent)])?;
+        let weights: Vec<(f64, f64)> = weights.into_iter().zip(tangent).collect();
+        Ok(py.allow_threads(|| self._amc::<(f64, f64), DualSemiring<f64>>(weights).into()))
+    }
+
+    /// Hessian-vector product of the WMC with respect to the positive and negative literal weights.
+    #[pyo3(signature = (pos_weights, neg_weights, pos_vector, neg_vector, algorithm = "auto"))]
+    fn hvp(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>, pos_vector: Vec<f64>, neg_vector: Vec<f64>, algorithm: &str) -> PyResult<(Vec<f64>, Vec<f64>)> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights), ("pos_vector", &pos_vector), ("neg_vector", &neg_vector)])?;
+        let algorithm = parse_algorithm(algorithm)?;
+        let (_, pos_grads, neg_grads) = py.allow_threads(|| self._hvp(pos_weights, neg_weights, pos_vector, neg_vector, algorithm))?;
+        Ok((pos_grads.into_iter().map(|g| g.1).collect(), neg_grads.into_iter().map(|g| g.1).collect()))
+    }
+
+    /// Hessian-vector product of the log-WMC with respect to the positive and negative literal weights.
+    #[pyo3(signature = (pos_weights, neg_weights, pos_vector, neg_vector, algorithm = "auto"))]
+    fn log_hvp(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>, pos_vector: Vec<f64>, neg_vector: Vec<f64>, algorithm: &str) -> PyResult<(Vec<f64>, Vec<f64>)> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights), ("pos_vector", &pos_vector), ("neg_vector", &neg_vector)])?;
+        let algorithm = parse_algorithm(algorithm)?;
+        let (result, pos_grads, neg_grads) = py.allow_threads(|| self._hvp(pos_weights, neg_weights, pos_vector, neg_vector, algorithm))?;
+        // ∇² log Z v = ∇²Z v / Z - ∇Z (∇Z · v) / Z²
+        let (z, z_dot) = (result.0, result.1);
+        let log_hvp = |g: DualSemiring<f64>| g.1 / z - g.0 * z_dot / (z * z);
+        Ok((pos_grads.into_iter().map(log_hvp).collect(), neg_grads.into_iter().map(log_hvp).collect()))
+    }
+
+    /// Joint marginals P(x_i, x_j) for every pair of the given variables, with P(x_i) on the diagonal.
+    fn pairwise_marginals(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>, vars: Vec<usize>) -> PyResult<Vec<Vec<f64>>> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights)])?;
+        if let Some(var) = vars.iter().find(|&&v| v == 0 || v > pos_weights.len()) {
+            return Err(PyValueError::new_err(format!("Variable {} is not between 1 and the number of weights ({})", var, pos_weights.len())));
+        }
+        Ok(py.allow_threads(|| self._pairwise_marginals(&pos_weights, &neg_weights, &vars))?)
+    }
+
+    #[pyo3(signature = (weights, algorithm = "auto"))]
+    fn _wmc_grad(&self, py: Python, weights: Vec<f64>, algorithm: &str) -> PyResult<(f64, Vec<f64>, f64)> {
+        let algorithm = parse_algorithm(algorithm)?;
+        Ok(py.allow_threads(|| self._amc_grad::<f64, f64>(weights, algorithm)))
+    }
+
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn _log_grad(&self, py: Python, pos_weights: Vec<f32>, neg_weights: Vec<f32>, algorithm: &str) -> PyResult<(f32, Vec<f32>, Vec<f32>, f64)> {
+        let algorithm = parse_algorithm(algorithm)?;
+        let (result, pos_grads, neg_grads, duration) = py.allow_threads(|| self._amc_grad2::<f32, LogSemiring>(pos_weights, neg_weights, algorithm));
+        check_nan([result].iter().chain(&pos_grads).chain(&neg_grads), "log-WMC or its gradient")?;
+        Ok((result, pos_grads, neg_grads, duration))
+    }
+
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn _fuzzy_grad(&self, py: Python, pos_weights: Vec<f32>, neg_weights: Vec<f32>, algorithm: &str) -> PyResult<(f32, Vec<f32>, Vec<f32>, f64)> {
+        let algorithm = parse_algorithm(algorithm)?;
+        Ok(py.allow_threads(|| self._amc_grad2::<f32, FuzzySemiring>(pos_weights, neg_weights, algorithm)))
+    }
+
+    /// Batched WMC, with weights of shape (nb_vars, batch_size).
+    fn wmc_np<'py>(&self, py: Python<'py>, weights: PyReadonlyArrayDyn<f64>) -> PyResult<&'py PyArray1<f64>> {
+        let (_, batch_size) = batch_shape(&weights, None)?;
+        let weights = from_pyarray(weights);
+        let result: Array1<f64> = py.allow_threads(|| self._amc::<Array1<f64>, NdarraySemiring>(weights).into());
+        Ok(broadcast(result, batch_size).to_pyarray(py))
+    }
+
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn _wmc_np_grad<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f64>, neg_weights: PyReadonlyArrayDyn<f64>, algorithm: &str) -> PyResult<(&'py PyArray1<f64>, &'py PyArray2<f64>, &'py PyArray2<f64>)> {
+        let (_, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let (pos_weights, neg_weights, algorithm) = (from_pyarray(pos_weights), from_pyarray(neg_weights), parse_algorithm(algorithm)?);
+        let (result, pos_grads, neg_grads) = py.allow_threads(|| self._amc_np_grad2::<Array1<f64>, NdarraySemiring>(pos_weights, neg_weights, algorithm));
+        Ok((
+            broadcast(result, batch_size).to_pyarray(py),
+            to_pyarray(py, pos_grads, batch_size),
+            to_pyarray(py, neg_grads, batch_size),
+        ))
+    }
+
+    /// Batched WMC and its gradient for real weights of either sign, of shape (nb_vars, batch_size).
+    /// The computation is in the signed log domain, so large circuits don't under- or overflow,
+    /// and every result is returned as a pair of signs and log-magnitudes.
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn _signed_log_wmc_np_grad<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f32>, neg_weights: PyReadonlyArrayDyn<f32>, algorithm: &str) -> PyResult<SignedLogGrad<'py>> {
+        let (_, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let to_signed_log = |w: Vec<Array1<f32>>| -> Vec<(Array1<bool>, Array1<f32>)> {
+            w.into_iter().map(|w| SignedLogNdarraySemiring::from_real(w).into()).collect()
+        };
+        let (pos_weights, neg_weights, algorithm) = (to_signed_log(from_pyarray(pos_weights)), to_signed_log(from_pyarray(neg_weights)), parse_algorithm(algorithm)?);
+        let (result, pos_grads, neg_grads) = py.allow_threads(|| self._amc_np_grad2::<_, SignedLogNdarraySemiring>(pos_weights, neg_weights, algorithm));
+        check_nan(result.1.iter().chain(pos_grads.iter().chain(&neg_grads).flat_map(|g| &g.1)), "log-WMC or its gradient")?;
+        let unzip = |grads: Vec<(Array1<bool>, Array1<f32>)>| -> SignedLogArrays<'py> {
+            let (signs, logs): (Vec<_>, Vec<_>) = grads.into_iter().unzip();
+            (to_pyarray(py, signs, batch_size), to_pyarray(py, logs, batch_size))
+        };
+        Ok((
+            (broadcast(result.0, batch_size).to_pyarray(py), broadcast(result.1, batch_size).to_pyarray(py)),
+            unzip(pos_grads),
+            unzip(neg_grads),
+        ))
+    }
+
+    /// Batched log-WMC, with log-weights of shape (nb_vars, batch_size).
+    fn log_wmc_np<'py>(&self, py: Python<'py>, weights: PyReadonlyArrayDyn<f64>) -> PyResult<&'py PyArray1<f64>> {
+        let (_, batch_size) = batch_shape(&weights, None)?;
+        let weights = from_pyarray(weights);
+        let result: Array1<f64> = py.allow_threads(|| self._amc::<Array1<f64>, LogNdarraySemiring<f64>>(weights).into());
+        check_nan(&result, "log-WMC")?;
+        Ok(broadcast(result, batch_size).to_pyarray(py))
+    }
+
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn _log_wmc_np_grad<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f32>, neg_weights: PyReadonlyArrayDyn<f32>, algorithm: &str) -> PyResult<(&'py PyArray1<f32>, &'py PyArray2<f32>, &'py PyArray2<f32>)> {
+        let (_, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let (pos_weights, neg_weights, algorithm) = (from_pyarray(pos_weights), from_pyarray(neg_weights), parse_algorithm(algorithm)?);
+        let (result, pos_grads, neg_grads) = py.allow_threads(|| self._amc_np_grad2::<Array1<f32>, LogNdarraySemiring>(pos_weights, neg_weights, algorithm));
+        check_nan(result.iter().chain(pos_grads.iter().flatten()).chain(neg_grads.iter().flatten()), "log-WMC or its gradient")?;
+        Ok((
+            broadcast(result, batch_size).to_pyarray(py),
+            to_pyarray(py, pos_grads, batch_size),
+            to_pyarray(py, neg_grads, batch_size),
+        ))
+    }
+
+    /// Batched forward pass of the WMC, returning the result and a tape for `backward`.
+    fn forward<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f64>, neg_weights: PyReadonlyArrayDyn<f64>) -> PyResult<(&'py PyArray1<f64>, Tape)> {
+        let (nb_vars, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let weights: PosNegWeights<NdarraySemiring> = PosNegWeights::from_vecs(from_pyarray(pos_weights), from_pyarray(neg_weights));
+        let buf = py.allow_threads(|| self._forward(&weights));
+        let result = buf.last().unwrap().0.clone();
+        Ok((broadcast(result, batch_size).to_pyarray(py), Tape { buf: TapeBuffer::Real(buf), nb_vars, batch_size }))
+    }
+
+    /// Batched forward pass of the log-WMC, returning the result and a tape for `backward`.
+    fn log_forward<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f32>, neg_weights: PyReadonlyArrayDyn<f32>) -> PyResult<(&'py PyArray1<f32>, Tape)> {
+        let (nb_vars, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let (pos_weights, neg_weights) = (from_pyarray(pos_weights), from_pyarray(neg_weights));
+        let weights: PosNegWeights<LogNdarraySemiring> = PosNegWeights::from_vecs(pos_weights.clone(), neg_weights.clone());
+        let buf = py.allow_threads(|| self._forward(&weights));
+        let result = buf.last().unwrap().0.clone();
+        check_nan(&result, "log-WMC")?;
+        Ok((broadcast(result, batch_size).to_pyarray(py), Tape { buf: TapeBuffer::Log(buf, pos_weights, neg_weights), nb_vars, batch_size }))
+    }
+
+    /// Vector-Jacobian product of a forward pass, for an upstream gradient of shape (batch_size,).
+    /// The gradients of a log tape are with respect to the log-weights.
+    #[pyo3(signature = (tape, upstream_grad, algorithm = "auto"))]
+    fn backward<'py>(&self, py: Python<'py>, tape: &Tape, upstream_grad: PyReadonlyArray1<f64>, algorithm: &str) -> PyResult<(&'py PyArray2<f64>, &'py PyArray2<f64>)> {
+        let algorithm = parse_algorithm(algorithm)?.batched();
+        let upstream_grad = upstream_grad.as_array().to_owned();
+        if upstream_grad.len() != 1 && upstream_grad.len() != tape.batch_size {
+            return Err(PyValueError::new_err(format!("The upstream gradient has length {}, but the batch size is {}", upstream_grad.len(), tape.batch_size)));
+        }
+        let upstream_grad = broadcast(upstream_grad, tape.batch_size);
+        let (pos_grads, neg_grads) = py.allow_threads(|| self._backward(tape, upstream_grad, algorithm))?;
+        Ok((to_pyarray(py, pos_grads, tape.batch_size), to_pyarray(py, neg_grads, tape.batch_size)))
+    }
+
+    /// Batched marginals P(x_i) and P(¬x_i) of all variables, with weights of shape (nb_vars, batch_size).
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn marginals<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f64>, neg_weights: PyReadonlyArrayDyn<f64>, algorithm: &str) -> PyResult<(&'py PyArray2<f64>, &'py PyArray2<f64>)> {
+        let (_, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let pos_weights: Vec<Array1<f64>> = from_pyarray(pos_weights).into_iter().map(|w| w.mapv(f64::ln)).collect();
+        let neg_weights: Vec<Array1<f64>> = from_pyarray(neg_weights).into_iter().map(|w| w.mapv(f64::ln)).collect();
+        let algorithm = parse_algorithm(algorithm)?;
+        let (pos_marginals, neg_marginals) = py.allow_threads(|| self._log_marginals_np(pos_weights, neg_weights, batch_size, algorithm))?;
+        let exp = |v: Vec<Array1<f64>>| v.into_iter().map(|x| x.mapv(f64::exp)).collect();
+        Ok((to_pyarray(py, exp(pos_marginals), batch_size), to_pyarray(py, exp(neg_marginals), batch_size)))
+    }
+
+    /// Batched log-marginals of all literals, with log-weights of shape (nb_vars, batch_size).
+    #[pyo3(signature = (pos_weights, neg_weights, algorithm = "auto"))]
+    fn log_marginals<'py>(&self, py: Python<'py>, pos_weights: PyReadonlyArrayDyn<f64>, neg_weights: PyReadonlyArrayDyn<f64>, algorithm: &str) -> PyResult<(&'py PyArray2<f64>, &'py PyArray2<f64>)> {
+        let (_, batch_size) = batch_shape(&pos_weights, Some(&neg_weights))?;
+        let (pos_weights, neg_weights, algorithm) = (from_pyarray(pos_weights), from_pyarray(neg_weights), parse_algorithm(algorithm)?);
+        let (pos_marginals, neg_marginals) = py.allow_threads(|| self._log_marginals_np(pos_weights, neg_weights, batch_size, algorithm))?;
+        Ok((to_pyarray(py, pos_marginals, batch_size), to_pyarray(py, neg_marginals, batch_size)))
+    }
+
+    /// Most probable explanation: the maximal weight of a model and its assignment.
+    /// Variables which are free or absent from the circuit are assigned their most likely value.
+    fn mpe(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>) -> Result<(f64, Vec<bool>)> {
+        py.allow_threads(|| self._mpe::<f64, ViterbiSemiring>(pos_weights, neg_weights))
+    }
+
+    fn log_mpe(&self, py: Python, pos_weights: Vec<f32>, neg_weights: Vec<f32>) -> Result<(f32, Vec<bool>)> {
+        py.allow_threads(|| self._mpe::<f32, TropicalSemiring>(pos_weights, neg_weights))
+    }
+
+    /// The k most probable models and their weights, sorted by decreasing weight.
+    /// Variables which are free or absent from the circuit take both values.
+    fn top_k(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>, k: usize) -> Result<Vec<(f64, Vec<bool>)>> {
+        py.allow_threads(|| self._top_k(pos_weights, neg_weights, k))
+    }
+
+    /// Expected value of an additive feature, which assigns a value to every literal.
+    fn expectation(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>, pos_features: Vec<f64>, neg_features: Vec<f64>) -> PyResult<f64> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights), ("pos_features", &pos_features), ("neg_features", &neg_features)])?;
+        let result = py.allow_threads(|| self._expectation(pos_weights, neg_weights, pos_features, neg_features))?;
+        Ok(result.1 / result.0)
+    }
+
+    fn log_expectation(&self, py: Python, pos_weights: Vec<f32>, neg_weights: Vec<f32>, pos_features: Vec<f32>, neg_features: Vec<f32>) -> PyResult<f32> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights), ("pos_features", &pos_features), ("neg_features", &neg_features)])?;
+        let result = py.allow_threads(|| self._expectation(
+            to_signed_log(pos_weights), to_signed_log(neg_weights),
+            real_to_signed_log(pos_features), real_to_signed_log(neg_features),
+        ))?;
+        let expectation = (result.1 / &result.0).to_real();
+        check_nan([expectation].iter(), "expectation")?;
+        Ok(expectation)
+    }
+
+    /// Entropy (in nats) of the distribution over models defined by the weights.
+    fn entropy(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>) -> PyResult<f64> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights)])?;
+        let ln = |w: &f64| if *w == 0. {0.} else {w.ln()};
+        let pos_features = pos_weights.iter().map(ln).collect();
+        let neg_features = neg_weights.iter().map(ln).collect();
+        let result = py.allow_threads(|| self._expectation(pos_weights, neg_weights, pos_features, neg_features))?;
+        Ok(result.0.ln() - result.1 / result.0)
+    }
+
+    fn log_entropy(&self, py: Python, pos_weights: Vec<f32>, neg_weights: Vec<f32>) -> PyResult<f32> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights)])?;
+        let ln = |w: &f32| if *w == f32::NEG_INFINITY {0.} else {*w};
+        let pos_features = pos_weights.iter().map(ln).collect();
+        let neg_features = neg_weights.iter().map(ln).collect();
+        let result = py.allow_threads(|| self._expectation(
+            to_signed_log(pos_weights), to_signed_log(neg_weights),
+            real_to_signed_log(pos_features), real_to_signed_log(neg_features),
+        ))?;
+        let entropy = result.0.1 - (result.1 / &result.0).to_real();
+        check_nan([entropy].iter(), "entropy")?;
+        Ok(entropy)
+    }
+
+    /// Covariance of two additive features f and g.
+    #[allow(clippy::too_many_arguments)]
+    fn covariance(&self, py: Python, pos_weights: Vec<f64>, neg_weights: Vec<f64>, pos_f: Vec<f64>, neg_f: Vec<f64>, pos_g: Vec<f64>, neg_g: Vec<f64>) -> PyResult<f64> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights), ("pos_f", &pos_f), ("neg_f", &neg_f), ("pos_g", &pos_g), ("neg_g", &neg_g)])?;
+        let result = py.allow_threads(|| self._second_order_expectation(pos_weights, neg_weights, pos_f, neg_f, pos_g, neg_g))?;
+        Ok(result.3 / result.0 - (result.1 / result.0) * (result.2 / result.0))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn log_covariance(&self, py: Python, pos_weights: Vec<f32>, neg_weights: Vec<f32>, pos_f: Vec<f32>, neg_f: Vec<f32>, pos_g: Vec<f32>, neg_g: Vec<f32>) -> PyResult<f32> {
+        check_lengths(&[("pos_weights", &pos_weights), ("neg_weights", &neg_weights), ("pos_f", &pos_f), ("neg_f", &neg_f), ("pos_g", &pos_g), ("neg_g", &neg_g)])?;
+        let result = py.allow_threads(|| self._second_order_expectation(
+            to_signed_log(pos_weights), to_signed_log(neg_weights),
+            real_to_signed_log(pos_f), real_to_signed_log(neg_f),
+            real_to_signed_log(pos_g), real_to_signed_log(neg_g),
+        ))?;
+        let (z, r, s, t) = (result.0, result.1, result.2, result.3);
+        let covariance = (t / &z).to_real() - (r / &z).to_real() * (s / &z).to_real();
+        check_nan([covariance].iter(), "covariance")?;
+        Ok(covariance)
+    }
+
+    /// The WMC as a symbolic arithmetic circuit, where every literal stands for its weight.
+    fn provenance(&self) -> RCircuit {
+        self._symbolic::<NodeSemiring>().0.into()
+    }
+
+    /// The WMC as a sparse polynomial in N[X], i.e. a list of (coefficient, monomial) pairs.
+    /// The number of monomials can be exponential, so this is meant for small circuits.
+    fn polynomial(&self, py: Python) -> PyResult<Vec<(PyObject, Vec<i32>)>> {
+        py.allow_threads(|| self._symbolic::<PolynomialSemiring>()).0.into_iter()
+            .map(|(monomial, coef)| Ok((bigint_to_py(py, &coef)?, monomial)))
+            .collect()
+    }
+
+    /// The monomials of the WMC polynomial in B[X], which drops the coefficients.
+    fn bool_polynomial(&self, py: Python) -> Vec<Vec<i32>> {
+        py.allow_threads(|| self._symbolic::<BoolPolynomialSemiring>()).0.into_iter().collect()
+    }
+
+    fn circuit_transform(&self) -> Result<Circuit> {
         let nb_vars = self.nb_vars() + 1;
-        let mut weights: Vec<Node> = vec![Node::Or(vec![]), Node::And(vec![])];
+        // The result has the constants, the literals and at most one node per node of the circuit.
+        if self.nb_nodes() + 2 * nb_vars as usize > MAX_NODES {
+            return Err(Error::InvalidCircuit(format!("Circuits have at most {} nodes", MAX_NODES)));
+        }
+        let mut weights = Circuit::new();
+        weights.push(NodeKind::Or, &[])?;
+        weights.push(NodeKind::And, &[])?;
         for i in 1..nb_vars {
-            weights.push(Node::Leaf(i as i32));
-            weights.push(Node::Leaf(-(i as i32)));
+            weights.push(NodeKind::Leaf(i as i32), &[])?;
+            weights.push(NodeKind::Leaf(-(i as i32)), &[])?;
         }
-        let weights: Rc<RefCell<Vec<Node>>> = Rc::new(RefCell::new(weights));
+        let weights: Rc<RefCell<Circuit>> = Rc::new(RefCell::new(weights));
         let pos_lit_map: Vec<CircuitSemiring> = (1..nb_vars).map(|i| CircuitSemiring {ix: 2*i as usize, context: Some(weights.clone())}).collect();
         let neg_lit_map: Vec<CircuitSemiring> = (1..nb_vars).map(|i| CircuitSemiring {ix: (2*i + 1) as usize, context: Some(weights.clone())}).collect();
         let amc_weights: PosNegWeights<CircuitSemiring> = PosNegWeights::from_vecs(pos_lit_map, neg_lit_map);
-        let amc_result = amc1::<CircuitSemiring>(&self, &amc_weights);
-        let result = amc_result.context.clone().unwrap().replace(vec![]);
-        Circuit {nodes: result}
+        let amc_result = amc1::<CircuitSemiring>(self, &amc_weights);
+        match amc_result.context {
+            Some(context) => Ok(context.replace(Circuit::new())),
+            // A constant circuit doesn't build any node, so the result is the constant.
+            None => {
+                let mut circuit = Circuit::new();
+                circuit.push(if amc_result.ix == 0 {NodeKind::Or} else {NodeKind::And}, &[])?;
+                Ok(circuit)
+            },
+        }
     }
 
-    fn log_wmc(&self, weights: Vec<f32>) -> f32 {
-        self._amc::<f32, LogSemiring>(weights).0
+    fn log_wmc(&self, py: Python, weights: Vec<f32>) -> Result<f32> {
+        let result = py.allow_threads(|| self._amc::<f32, LogSemiring>(weights).0);
+        check_nan([result].iter(), "log-WMC")?;
+        Ok(result)
     }
 
     fn depth_width(&self) -> (usize, Vec<usize>) {
-        let mut depths: Vec<usize> = vec![0; self.nodes.len()];
+        let depths = self.depths();
         let mut widths: Vec<usize> = vec![];
-        for (i, node) in self.nodes.iter().enumerate() {
-            depths[i] = match node {
-                Node::Or(children) => {
-                    children.iter().map(|&j| depths[j]).max().unwrap_or(0) + 1
-                },
-                Node::And(children) => {
-                    children.iter().map(|&j| depths[j]).max().unwrap_or(0) + 1
-                },
-                _ => 1,
-            };
-            if depths[i] >= widths.len() {
-                widths.resize(depths[i] + 1, 0);
+        for &depth in &depths {
+            if depth >= widths.len() {
+                widths.resize(depth + 1, 0);
             }
-            widths[depths[i]] += 1;
+            widths[depth] += 1;
         }
         let max_depth = *depths.iter().max().unwrap();
         (max_depth, widths)
     }
 
     fn node_width(&self) -> usize {
-        self.nodes.iter().map(|node| match node {
-            Node::Or(children) => children.len(),
-            Node::And(children) => children.len(),
-            _ => 0,
-        }).max().unwrap()
+        self.offsets.windows(2).map(|w| w[1] - w[0]).max().unwrap()
+    }
+}
+
+fn parse_algorithm(algorithm: &str) -> PyResult<GradAlgorithm> {
+    algorithm.parse().map_err(PyValueError::new_err)
+}
+
+fn check_tape<T>(circuit: &Circuit, buf: &[T]) -> Result<()> {
+    if buf.len() == circuit.nb_nodes() {
+        Ok(())
+    } else {
+        Err(Error::InvalidCircuit("The tape was recorded on a different circuit".to_string()))
+    }
+}
+
+/// Checks that the named vectors all have the same length.
+fn check_lengths<T>(vectors: &[(&str, &[T])]) -> PyResult<()> {
+    match vectors.iter().find(|(_, v)| v.len() != vectors[0].1.len()) {
+        Some((name, v)) => Err(PyValueError::new_err(format!("{} has length {}, but {} has length {}", name, v.len(), vectors[0].0, vectors[0].1.len()))),
+        None => Ok(()),
+    }
+}
+
+fn check_lits(lits: &[i32]) -> PyResult<()> {
+    if lits.contains(&0) {
+        Err(PyValueError::new_err("Variables are numbered from 1"))
+    } else if lits.contains(&i32::MIN) {
+        Err(PyValueError::new_err(format!("Literal {} has no negation", i32::MIN)))
+    } else {
+        Ok(())
     }
 }
 
+fn to_rational(x: &PyAny) -> PyResult<BigRational> {
+    // Python ints, floats and fractions all have an exact integer ratio
+    let (numer, denom): (&PyAny, &PyAny) = x.call_method0("as_integer_ratio")?.extract()?;
+    Ok(BigRational::new(bigint_from_py(numer)?, bigint_from_py(denom)?))
+}
+
+// pyo3 has no BigInt conversions under the limited API, so we go through int.to_bytes/from_bytes.
+fn bigint_from_py(x: &PyAny) -> PyResult<BigInt> {
+    let py = x.py();
+    let nb_bits: usize = x.call_method0("bit_length")?.extract()?;
+    let kwargs = [("signed", true)].into_py_dict(py);
+    let bytes: &PyBytes = x.call_method("to_bytes", (nb_bits / 8 + 1, "little"), Some(kwargs))?.downcast()?;
+    Ok(BigInt::from_signed_bytes_le(bytes.as_bytes()))
+}
+
+fn bigint_to_py(py: Python, x: &BigInt) -> PyResult<PyObject> {
+    let kwargs = [("signed", true)].into_py_dict(py);
+    let bytes = PyBytes::new(py, &x.to_signed_bytes_le());
+    Ok(py.get_type::<PyLong>().call_method("from_bytes", (bytes, "little"), Some(kwargs))?.into())
+}
+
+fn to_signed_log(v: Vec<f32>) -> Vec<SignedLogSemiring> {
+    v.into_iter().map(SignedLogSemiring::from).collect()
+}
+
+fn real_to_signed_log(v: Vec<f32>) -> Vec<SignedLogSemiring> {
+    v.into_iter().map(SignedLogSemiring::from_real).collect()
+}
+
+/// Batched evidence as an int64 array, from an array of any integer or boolean dtype.
+fn evidence_array(evidence: &PyAny) -> PyResult<PyReadonlyArrayDyn<'_, i64>> {
+    let kind = evidence.getattr("dtype").ok()
+        .and_then(|dtype| dtype.downcast::<PyArrayDescr>().ok())
+        .map(|dtype| dtype.kind());
+    let evidence = match kind {
+        Some(b'i') => evidence.call_method1("astype", ("int64",))?,
+        // Only the sign matters, and unsigned values may not fit into int64.
+        Some(b'u' | b'b') => evidence.call_method1("astype", ("bool",))?.call_method1("astype", ("int64",))?,
+        _ => return Err(PyTypeError::new_err("The evidence must be a list of literals, or an array of integers or booleans")),
+    };
+    evidence.extract()
+}
+
+/// The shape (nb_vars, batch_size) of batched weights, which must match the negative weights if given.
+fn batch_shape<T: Element>(weights: &PyReadonlyArrayDyn<T>, neg_weights: Option<&PyReadonlyArrayDyn<T>>) -> PyResult<(usize, usize)> {
+    if weights.ndim() != 2 {
+        return Err(PyValueError::new_err(format!("Batched weights must have shape (nb_vars, batch_size), got {:?}", weights.shape())));
+    }
+    if let Some(neg_weights) = neg_weights {
+        if neg_weights.shape() != weights.shape() {
+            return Err(PyValueError::new_err(format!("The positive and negative weights have shapes {:?} and {:?}", weights.shape(), neg_weights.shape())));
+        }
+    }
+    Ok((weights.shape()[0], weights.shape()[1]))
+}
+
 fn from_pyarray<T>(v: PyReadonlyArrayDyn<T>) -> Vec<Array1<T>>
 where T: Float + Element
 {
@@ -166,19 +1191,294 @@ where T: Float + Element
         .collect()
 }
 
-fn to_pyarray<T>(py: Python, v: Vec<Array1<T>>) -> &PyArray2<T>
-where T: Float + Element
+/// Stacks the arrays into shape (len, batch_size), which also holds when there are none.
+fn to_pyarray<T>(py: Python<'_>, v: Vec<Array1<T>>, batch_size: usize) -> &PyArray2<T>
+where T: Element
 {
-    let size: usize = v.iter().map(|x| x.len()).max().unwrap();
-    let v: Vec<Array1<T>> = v.into_iter().map(|x| {
-        if x.len() < size {
-            // Zero/one semiring elements need to be padded
-            if T::is_zero(&x[0]) {Array1::zeros(size) } else {Array1::ones(size)}
-        } else {
-            x
-        }
-    }).collect();
-    let v = v.iter().map(|arr| arr.view()).collect::<Vec<_>>();
-    let v: Array2<T> = stack(Axis(0), &v).unwrap();
+    // Zero/one semiring elements need to be broadcast to the batch size
+    let nb_rows = v.len();
+    let data: Vec<T> = v.into_iter().flat_map(|x| broadcast(x, batch_size)).collect();
+    let v: Array2<T> = Array2::from_shape_vec((nb_rows, batch_size), data).unwrap();
     v.to_pyarray(py)
-}
\ No newline at end of file
+}
+
+/// Reproducible random generator when a seed is given.
+fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+fn broadcast<T: Clone>(v: Array1<T>, size: usize) -> Array1<T> {
+    if v.len() == size {
+        v
+    } else {
+        v.broadcast(size).unwrap().to_owned()
+    }
+}
+
+/// x1 ∨ (¬x1 ∧ x2), which is not smooth.
+#[cfg(test)]
+pub(crate) fn non_smooth() -> Circuit {
+    let mut circuit = Circuit::new();
+    let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+    let not_x1 = circuit.push(NodeKind::Leaf(-1), &[]).unwrap();
+    let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+    let and = circuit.push(NodeKind::And, &[not_x1, x2]).unwrap();
+    circuit.push(NodeKind::Or, &[x1, and]).unwrap();
+    circuit
+}
+
+#[cfg(test)]
+mod tests {
+    use numpy::ndarray::array;
+    use super::*;
+
+    #[test]
+    fn mpe_maximizes_over_free_variables() {
+        let circuit = non_smooth();
+        let (value, assignment) = circuit._mpe::<f64, ViterbiSemiring>(vec![0.5, 0.5], vec![0.9, 0.5]).unwrap();
+        assert!((value - 0.45).abs() < 1e-12);
+        assert_eq!(assignment, vec![false, true]);
+
+        let (value, assignment) = circuit._mpe::<f32, TropicalSemiring>(vec![0.5f32.ln(), 0.5f32.ln()], vec![0.9f32.ln(), 0.5f32.ln()]).unwrap();
+        assert!((value - 0.45f32.ln()).abs() < 1e-6);
+        assert_eq!(assignment, vec![false, true]);
+    }
+
+    #[test]
+    fn top_k_ranks_complete_models() {
+        let circuit = non_smooth();
+        let models = circuit._top_k(vec![0.5, 0.5], vec![0.9, 0.5], 2).unwrap();
+        assert_eq!(models.len(), 2);
+        assert!((models[0].0 - 0.45).abs() < 1e-12);
+        assert_eq!(models[0].1, vec![false, true]);
+        assert!((models[1].0 - 0.25).abs() < 1e-12);
+        assert!(models[1].1[0]);
+
+        let models = circuit._top_k(vec![0.5, 0.5, 0.5], vec![0.9, 0.5, 0.5], 10).unwrap();
+        assert_eq!(models.len(), 6);
+        assert!((models.iter().map(|m| m.0).sum::<f64>() - 0.95).abs() < 1e-12);
+    }
+
+    #[test]
+    fn expectations_count_free_variables() {
+        let circuit = non_smooth();
+        let (pos_weights, neg_weights) = (vec![0.5, 0.5, 0.2], vec![0.9, 0.5, 0.8]);
+        // The models are x1 ∧ x2, x1 ∧ ¬x2 and ¬x1 ∧ x2 (weights .25, .25, .45), and x3 is free.
+        let result = circuit._expectation(pos_weights.clone(), neg_weights.clone(), vec![0., 1., 1.], vec![0.; 3]).unwrap();
+        assert!((result.0 - 0.95).abs() < 1e-12);
+        assert!((result.1 / result.0 - (0.7 / 0.95 + 0.2)).abs() < 1e-12);
+
+        let result = circuit._second_order_expectation(pos_weights, neg_weights, vec![1., 0., 0.], vec![0.; 3], vec![0., 1., 0.], vec![0.; 3]).unwrap();
+        let (e_f, e_g, e_fg) = (result.1 / result.0, result.2 / result.0, result.3 / result.0);
+        assert!((e_f - 0.5 / 0.95).abs() < 1e-12);
+        assert!((e_g - 0.7 / 0.95).abs() < 1e-12);
+        assert!((e_fg - 0.25 / 0.95).abs() < 1e-12);
+    }
+
+    #[test]
+    fn model_count_includes_free_variables() {
+        let circuit = non_smooth();
+        assert_eq!(circuit._mc::<i32>().unwrap(), 3);
+        assert_eq!(circuit._mc::<BigInt>().unwrap(), BigInt::from(3));
+
+        // (x1 ∧ x3) ∨ ¬x1 over x1, x2, x3, where x2 is absent: 2 + 4 models.
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let not_x1 = circuit.push(NodeKind::Leaf(-1), &[]).unwrap();
+        let x3 = circuit.push(NodeKind::Leaf(3), &[]).unwrap();
+        let and = circuit.push(NodeKind::And, &[x1, x3]).unwrap();
+        circuit.push(NodeKind::Or, &[and, not_x1]).unwrap();
+        assert_eq!(circuit._mc::<BigInt>().unwrap(), BigInt::from(6));
+    }
+
+    #[test]
+    fn polynomials_list_the_paths() {
+        let circuit = non_smooth();
+        let polynomial = circuit._symbolic::<PolynomialSemiring>();
+        assert_eq!(polynomial.0.into_iter().collect::<Vec<_>>(), vec![(vec![-1, 2], BigInt::from(1)), (vec![1], BigInt::from(1))]);
+        let monomials = circuit._symbolic::<BoolPolynomialSemiring>();
+        assert_eq!(monomials.0.into_iter().collect::<Vec<_>>(), vec![vec![-1, 2], vec![1]]);
+    }
+
+    #[test]
+    fn jvp_is_the_directional_derivative() {
+        let circuit = non_smooth();
+        // With the negative weights 1 - w, the WMC is w1 + (1 - w1) w2.
+        let (value, tangent): (f64, f64) = circuit._amc::<(f64, f64), DualSemiring<f64>>(vec![(0.5, 1.), (0.25, 2.)]).into();
+        assert!((value - 0.625).abs() < 1e-12);
+        assert!((tangent - (0.75 + 2. * 0.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn pairwise_marginals_count_free_variables() {
+        let circuit = non_smooth();
+        let marginals = circuit._pairwise_marginals(&[0.5, 0.5], &[0.9, 0.5], &[1, 2]).unwrap();
+        let expected = [[0.5 / 0.95, 0.25 / 0.95], [0.25 / 0.95, 0.7 / 0.95]];
+        for (row, expected) in marginals.iter().zip(expected) {
+            assert!(row.iter().zip(expected).all(|(x, y)| (x - y).abs() < 1e-12));
+        }
+    }
+
+    #[test]
+    fn hvp_of_a_multilinear_wmc() {
+        // Z = p1 p2 + p1 n2 + n1 p2 in the smoothed circuit, so ∂²Z/∂p1∂p2 = 1 and ∂²Z/∂n1∂p2 = 1.
+        let circuit = non_smooth();
+        for algorithm in [GradAlgorithm::Auto, GradAlgorithm::Alg1, GradAlgorithm::Naive, GradAlgorithm::Cancel] {
+            let (_, pos_grads, neg_grads) = circuit._hvp(vec![0.5, 0.5], vec![0.9, 0.5], vec![0., 1.], vec![0., 0.], algorithm).unwrap();
+            assert_eq!(pos_grads.iter().map(|g| g.1).collect::<Vec<_>>(), vec![1., 0.]);
+            assert_eq!(neg_grads.iter().map(|g| g.1).collect::<Vec<_>>(), vec![1., 0.]);
+        }
+    }
+
+    #[test]
+    fn levels_are_cached_until_push() {
+        let mut circuit = non_smooth();
+        assert_eq!(circuit.levels(), &[vec![0, 1, 2], vec![3], vec![4]]);
+        circuit.push(NodeKind::And, &[4, 2]).unwrap();
+        assert_eq!(circuit.levels(), &[vec![0, 1, 2], vec![3], vec![4], vec![5]]);
+    }
+
+    #[test]
+    fn derived_circuits_share_the_pool() {
+        let mut circuit = non_smooth();
+        circuit.pool = Some(Arc::new(ThreadPoolBuilder::new().num_threads(2).build().unwrap()));
+        let smoothed = smooth(&circuit, None).unwrap().with_pool_of(&circuit);
+        assert!(Arc::ptr_eq(smoothed.pool.as_ref().unwrap(), circuit.pool.as_ref().unwrap()));
+        assert_eq!(circuit._mc::<i32>().unwrap(), 3);
+    }
+
+    #[test]
+    fn mpe_assigns_absent_variables() {
+        let circuit = non_smooth();
+        let (value, assignment) = circuit._mpe::<f64, ViterbiSemiring>(vec![0.5, 0.5, 0.2], vec![0.9, 0.5, 0.8]).unwrap();
+        assert!((value - 0.36).abs() < 1e-12);
+        assert_eq!(assignment, vec![false, true, false]);
+    }
+
+    #[test]
+    fn knowledge_compilation_queries() {
+        let circuit = non_smooth();
+        assert!(circuit._consistent_with(&[]));
+        assert!(!circuit._consistent_with(&[-1, -2]));
+        assert!(circuit._consistent_with(&[-1, 3]));
+        assert_eq!(circuit._probability_given(&[]), BigRational::new(3.into(), 4.into()));
+        assert_eq!(circuit._probability_given(&[2]), BigRational::one());
+        assert_eq!(circuit._probability_given(&[-1]), BigRational::new(1.into(), 2.into()));
+    }
+
+    /// x1 ∧ ... ∧ x_n, with the last variable negated if `flip`.
+    fn conjunction(n: i32, flip: bool) -> Circuit {
+        let mut circuit = Circuit::new();
+        let leaves: Vec<usize> = (1..=n).map(|v| {
+            let lit = if flip && v == n { -v } else { v };
+            circuit.push(NodeKind::Leaf(lit), &[]).unwrap()
+        }).collect();
+        circuit.push(NodeKind::And, &leaves).unwrap();
+        circuit
+    }
+
+    #[test]
+    fn equivalence_of_circuits() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let circuit = non_smooth();
+        assert!(circuit._is_equivalent(&smooth(&circuit, Some(3)).unwrap(), 8, 1e-9, &mut rng));
+        assert!(!circuit._is_equivalent(&conjunction(2, false), 8, 1e-9, &mut rng));
+        let mut inconsistent = Circuit::new();
+        inconsistent.push(NodeKind::Or, &[]).unwrap();
+        assert!(inconsistent._is_equivalent(&inconsistent, 8, 1e-9, &mut rng));
+        assert!(!inconsistent._is_equivalent(&circuit, 8, 1e-9, &mut rng));
+    }
+
+    #[test]
+    fn equivalence_without_underflow() {
+        // The WMC of both is below the smallest f64.
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(conjunction(3000, false)._is_equivalent(&conjunction(3000, false), 8, 1e-9, &mut rng));
+        assert!(!conjunction(3000, false)._is_equivalent(&conjunction(3000, true), 8, 1e-9, &mut rng));
+    }
+
+    #[test]
+    fn queries_condition_on_the_evidence() {
+        let circuit = non_smooth();
+        assert!((circuit._query(vec![0.5, 0.5], &[], &[1]).unwrap() - 2. / 3.).abs() < 1e-12);
+        assert!((circuit._query(vec![0.5, 0.5], &[], &[2]).unwrap() - 2. / 3.).abs() < 1e-12);
+        assert!((circuit._query(vec![0.5, 0.5], &[1], &[3]).unwrap() - 0.5).abs() < 1e-12);
+        assert!((circuit._query(vec![0.5, 0.5], &[2], &[1]).unwrap() - 0.5).abs() < 1e-12);
+        assert_eq!(circuit._query(vec![0.5, 0.5], &[-1], &[2]).unwrap(), 1.);
+        assert!(matches!(circuit._query(vec![0.5, 0.5], &[-1, -2], &[1]), Err(Error::Numerical(_))));
+        // x2 is observed in the first batch element, and ¬x1 in the second.
+        let evidence = array![[0i64, -1], [1, 0]].into_dyn();
+        assert_eq!(circuit._query_batch(&[0.5, 0.5], evidence.view(), &[1]).unwrap(), array![0.5, 0.]);
+        let evidence = array![[-1i64, 0], [-1, 0]].into_dyn();
+        assert!(matches!(circuit._query_batch(&[0.5, 0.5], evidence.view(), &[1]), Err(Error::Numerical(_))));
+    }
+
+    #[test]
+    fn samples_are_models_of_the_evidence() {
+        let circuit = non_smooth();
+        let samples = circuit._sample(vec![0.5, 0.5, 0.5], 200, None, Some(0)).unwrap();
+        assert!(samples.iter().all(|s| s.len() == 3 && (s[0] || s[1])));
+        // ¬x1 ∧ x2 has probability 0.25 / 0.75, and x3 is free.
+        let nb_negative = samples.iter().filter(|s| !s[0]).count();
+        assert!((30..110).contains(&nb_negative));
+        assert!(samples.iter().any(|s| s[2]) && samples.iter().any(|s| !s[2]));
+        assert_eq!(samples, circuit._sample(vec![0.5, 0.5, 0.5], 200, None, Some(0)).unwrap());
+        let samples = circuit._sample(vec![0.5, 0.5], 20, Some(vec![-1]), Some(0)).unwrap();
+        assert!(samples.iter().all(|s| s == &[false, true]));
+        // Given x2, the models x1 ∧ x2 and ¬x1 ∧ x2 are equally likely.
+        let samples = circuit._sample(vec![0.5, 0.5], 200, Some(vec![2]), Some(0)).unwrap();
+        assert!(samples.iter().all(|s| s[1]));
+        assert!((70..130).contains(&samples.iter().filter(|s| s[0]).count()));
+        assert!(matches!(circuit._sample(vec![0.5, 0.5], 1, Some(vec![-1, -2]), None), Err(Error::Numerical(_))));
+    }
+
+    #[test]
+    fn backward_through_a_tape() {
+        let circuit = smooth(&non_smooth(), Some(2)).unwrap();
+        let (pos_weights, neg_weights) = (vec![array![0.5, 1.], array![0.5, 1.]], vec![array![0.9, 1.], array![0.5, 1.]]);
+        let weights: PosNegWeights<NdarraySemiring> = PosNegWeights::from_vecs(pos_weights, neg_weights);
+        let tape = Tape { buf: TapeBuffer::Real(circuit._forward(&weights)), nb_vars: 2, batch_size: 2 };
+        let (pos_grads, neg_grads) = circuit._backward(&tape, array![1., 2.], GradAlgorithm::Cancel).unwrap();
+        assert_eq!(pos_grads, vec![array![1., 4.], array![1.4, 4.]]);
+        assert_eq!(neg_grads, vec![array![0.5, 2.], array![0.5, 2.]]);
+        assert!(matches!(non_smooth()._backward(&tape, array![1., 2.], GradAlgorithm::Cancel), Err(Error::InvalidCircuit(_))));
+    }
+
+    #[test]
+    fn log_marginals_of_all_literals() {
+        let circuit = non_smooth();
+        let ln = |w: [f64; 2]| Array1::from(w.to_vec()).mapv(f64::ln);
+        let (pos_weights, neg_weights) = (vec![ln([0.5, 1.]), ln([0.5, 1.])], vec![ln([0.9, 1.]), ln([0.5, 1.])]);
+        let (pos_marginals, neg_marginals) = circuit._log_marginals_np(pos_weights, neg_weights, 2, GradAlgorithm::Auto).unwrap();
+        // The models are x1 (with either value of x2) and ¬x1 ∧ x2.
+        let expected_pos = [[0.5 / 0.95, 2. / 3.], [0.7 / 0.95, 2. / 3.]];
+        let expected_neg = [[0.45 / 0.95, 1. / 3.], [0.25 / 0.95, 1. / 3.]];
+        for (marginals, expected) in [(pos_marginals, expected_pos), (neg_marginals, expected_neg)] {
+            for (m, e) in marginals.iter().zip(expected) {
+                assert!(m.iter().zip(e).all(|(m, e)| (m.exp() - e).abs() < 1e-12));
+            }
+        }
+    }
+
+    #[test]
+    fn flat_layout() {
+        let circuit = non_smooth();
+        assert_eq!(circuit.nb_nodes(), 5);
+        assert_eq!(circuit.nodes().collect::<Vec<_>>(), vec![Node::Leaf(1), Node::Leaf(-1), Node::Leaf(2), Node::And(&[1, 2]), Node::Or(&[0, 3])]);
+        assert_eq!(circuit.parents(), vec![vec![4], vec![3], vec![3], vec![4], vec![]]);
+        assert_eq!(circuit.scopes(), vec![vec![1], vec![1], vec![2], vec![1, 2], vec![1, 2]]);
+        assert_eq!(circuit.depths(), vec![1, 1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn children_come_before_their_parents() {
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        assert!(matches!(circuit.push(NodeKind::And, &[x1, x1 + 1]), Err(Error::InvalidCircuit(_))));
+        assert!(matches!(circuit.push(NodeKind::Or, &[x1 + 2]), Err(Error::InvalidCircuit(_))));
+        assert_eq!(circuit.nb_nodes(), 1);
+    }
+}
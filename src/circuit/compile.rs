@@ -1,13 +1,14 @@
 use std::rc::Rc;
 use std::process::Command;
 use tempfile::NamedTempFile;
-use pyo3::Python;
+use pyo3::{PyResult, Python};
 use std::io::Write;
 use std::ops::Add;
 use std::path::Path;
 use crate::circuit::circuit::Circuit;
-use crate::circuit::parser::load_d4;
+use crate::circuit::parser::parse_d4;
 use crate::circuit::rcircuit::RNode;
+use crate::error::{Error, Result};
 
 
 use crate::circuit::tseitin::tseitin_transform;
@@ -25,32 +26,30 @@ if cfg!(target_os = "linux") & cfg!(target_arch = "x86_64") {
 };
 
 
-fn get_lib_path() -> String {
-    Python::with_gil(|py| {
-        let importlib = py.import("importlib.resources").unwrap();
-        let path = importlib.getattr("files").unwrap()
-            .call1(("kompyle",)).unwrap();
-        path.to_string()
-    })
+fn get_lib_path(py: Python) -> PyResult<String> {
+    let importlib = py.import("importlib.resources")?;
+    let path = importlib.getattr("files")?
+        .call1(("kompyle",))?;
+    Ok(path.to_string())
 }
 
 
-fn set_executable(path: &str) {
+fn set_executable(path: &str) -> Result<()> {
     Command::new("chmod")
         .arg("+x")
         .arg(path)
-        .output()
-        .expect("Failed to set executable");
+        .output()?;
+    Ok(())
 }
 
 
-pub fn to_dimacs(node: Rc<RNode>) -> String {
+pub fn to_dimacs(node: Rc<RNode>) -> Result<String> {
     // Assumes that the circuit is in CNF
-    match *node {
+    Ok(match *node {
         RNode::Sum(ref nodes) => {
             nodes.iter()
                 .map(|n| to_dimacs(n.clone()))
-                .collect::<Vec<String>>()
+                .collect::<Result<Vec<String>>>()?
                 .join(" ").add(" 0")
         }
         RNode::Prod(ref nodes) => {
@@ -58,58 +57,65 @@ pub fn to_dimacs(node: Rc<RNode>) -> String {
             let header: String = format!("p cnf {} {}\n", nb_vars, nodes.len());
             let body = nodes.iter()
                 .map(|n| to_dimacs(n.clone()))
-                .collect::<Vec<String>>()
+                .collect::<Result<Vec<String>>>()?
                 .join("\n");
             header + &body
         }
         RNode::Val(value) => value.to_string(),
-        _ => panic!("Unexpected true/false node"),
-    }
+        _ => return Err(Error::InvalidCircuit("DIMACS can't contain true/false nodes, simplify the circuit first".to_string())),
+    })
 }
 
-pub fn compile(node: Rc<RNode>, nb_vars: u32, solver_name: String) -> Result<Circuit, std::io::Error> {
-    let node = tseitin_transform(node.simplify(), nb_vars);
-    let dimacs = to_dimacs(node);
-    let mut file_dimacs = NamedTempFile::new()?;
-    file_dimacs.write_all(dimacs.as_bytes())?;
-    let file_nnf = NamedTempFile::new()?;
-
-    if solver_name == "d4" {
-        _compile_d4(file_dimacs.path(), file_nnf.path())
-    } else if solver_name == "sharpsat" {
-        _compile_sharpsat(file_dimacs.path(), file_nnf.path())
-    } else {
-        panic!("Unknown solver")
-    }
+/// Compiles the circuit with the given solver. The solver runs, and its output is parsed,
+/// without holding the GIL, so the solver path is looked up beforehand.
+pub fn compile(py: Python, node: Rc<RNode>, nb_vars: u32, solver_name: String) -> PyResult<Circuit> {
+    let node = tseitin_transform(node.simplify(), nb_vars)?;
+    let dimacs = to_dimacs(node)?;
+    let lib_path = get_lib_path(py)?;
+
+    Ok(py.allow_threads(move || {
+        let mut file_dimacs = NamedTempFile::new()?;
+        file_dimacs.write_all(dimacs.as_bytes())?;
+        let file_nnf = NamedTempFile::new()?;
+
+        if solver_name == "d4" {
+            _compile_d4(&lib_path, file_dimacs.path(), file_nnf.path())
+        } else if solver_name == "sharpsat" {
+            _compile_sharpsat(&lib_path, file_dimacs.path(), file_nnf.path())
+        } else {
+            Err(Error::Compilation(format!("Unknown solver '{}'", solver_name)))
+        }
+    }).map_err(|e| match e {
+        Error::Io(e) => Error::CompilationIo(e),
+        e => e,
+    })?)
 }
 
-fn _compile_d4(dimacs_file: &Path, out_file: &Path) -> Result<Circuit, std::io::Error> {
+fn _compile_d4(lib_path: &str, dimacs_file: &Path, out_file: &Path) -> Result<Circuit> {
     // run d4 solver
-    let d4_path = format!("{}/lib/{}/d4", get_lib_path(), PLATFORM);
-    set_executable(&d4_path);
+    let d4_path = format!("{}/lib/{}/d4", lib_path, PLATFORM);
+    set_executable(&d4_path)?;
     let output = Command::new(d4_path)
         .arg("-dDNNF")
         .arg(dimacs_file)
         .arg(format!("-out={}", out_file.display()))
         .output()?;
-    assert!(output.status.success());
-    Ok(load_d4(out_file.to_str().unwrap())?)
+    if !output.status.success() {
+        return Err(Error::Compilation(format!("d4 failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    parse_d4(&out_file.to_string_lossy())
 }
 
 
-fn _compile_sharpsat(dimacs_file: &Path, out_file: &Path) -> Result<Circuit, std::io::Error> {
-    let solver_folder = format!("{}/lib/{}", get_lib_path(), PLATFORM);
+fn _compile_sharpsat(lib_path: &str, _dimacs_file: &Path, _out_file: &Path) -> Result<Circuit> {
+    let solver_folder = format!("{}/lib/{}", lib_path, PLATFORM);
     let solver_path = format!("{}/sharpSAT", solver_folder);
     let tmp_path = format!("{}/tmp", solver_folder);
 
-    println!("Solver path: {}", solver_path);
-    println!("TMP path: {}", tmp_path);
-    set_executable(&solver_path);
+    set_executable(&solver_path)?;
     if !Path::new(tmp_path.as_str()).exists() {
         std::fs::create_dir(tmp_path.clone())?;
     }
-    println!("RUNNING sharpSAT");
-    println!("Current directory: {}", std::env::current_dir().unwrap().display());
 
     let output = Command::new(solver_path)
         //.current_dir(solver_folder)
@@ -121,11 +127,8 @@ fn _compile_sharpsat(dimacs_file: &Path, out_file: &Path) -> Result<Circuit, std
         //.arg(dimacs_file)
         // .arg(format!("-dDNNF_out {}", out_file.display()))
         .output()?;
-    // print output
-    println!("OUTPUT:");
-    println!("{}", String::from_utf8_lossy(&output.stdout));
-    println!("{}", String::from_utf8_lossy(&output.stderr));
-
-    assert!(output.status.success());
-    todo!()
+    if !output.status.success() {
+        return Err(Error::Compilation(format!("sharpSAT failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Err(Error::Compilation("Reading the sharpSAT output is not supported yet".to_string()))
 }
\ No newline at end of file
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use pyo3::{pyclass, pymethods, Py, PyRef, PyResult, Python};
+use pyo3::exceptions::PyValueError;
+use crate::circuit::amc::{amc_update, GradAlgorithm};
+use crate::circuit::circuit::{Circuit, Node};
+use crate::circuit::weights::{PosNegWeights, Weights};
+
+/// WMC evaluator which keeps the node values alive, so that sparse weight
+/// updates only re-evaluate the ancestors of the affected leaves.
+#[pyclass]
+pub struct IncrementalEvaluator {
+    circuit: Py<Circuit>,
+    parents: Vec<Vec<usize>>,
+    leaves: HashMap<i32, Vec<usize>>,
+    weights: PosNegWeights<f64>,
+    buf: Vec<f64>,
+    // Gradient for the current weights, cleared by every update
+    grads: Option<(Vec<f64>, Vec<f64>)>,
+}
+
+impl IncrementalEvaluator {
+    pub(crate) fn new(circuit: PyRef<'_, Circuit>, weights: PosNegWeights<f64>, buf: Vec<f64>) -> Self {
+        let mut leaves: HashMap<i32, Vec<usize>> = HashMap::new();
+        for (i, node) in circuit.nodes().enumerate() {
+            if let Node::Leaf(lit) = node {
+                leaves.entry(lit).or_default().push(i);
+            }
+        }
+        IncrementalEvaluator {
+            parents: circuit.parents(),
+            circuit: circuit.into(),
+            leaves,
+            weights,
+            buf,
+            grads: None,
+        }
+    }
+}
+
+#[pymethods]
+impl IncrementalEvaluator {
+    fn value(&self) -> f64 {
+        *self.buf.last().unwrap()
+    }
+
+    /// Sets the weights of the given (literal, weight) pairs and returns the new WMC.
+    fn update(&mut self, py: Python<'_>, updates: Vec<(i32, f64)>) -> PyResult<f64> {
+        // All the updates are checked first, so that a bad one leaves the weights unchanged.
+        if let Some((lit, _)) = updates.iter().find(|(lit, _)| *lit == 0 || lit.unsigned_abs() as usize > self.weights.len()) {
+            return Err(PyValueError::new_err(format!("Literal {} has no weight", lit)));
+        }
+        let mut dirty = vec![];
+        for (lit, weight) in updates {
+            self.weights.set(lit, weight);
+            dirty.extend(self.leaves.get(&lit).into_iter().flatten());
+        }
+        self.grads = None;
+        let circuit = self.circuit.borrow(py);
+        let (circuit, parents, weights, buf): (&Circuit, _, _, _) = (&circuit, &self.parents, &self.weights, &mut self.buf);
+        Ok(py.allow_threads(|| amc_update(circuit, parents, weights, buf, dirty)))
+    }
+
+    /// Gradient of the WMC with respect to the positive and negative weights,
+    /// backpropagated through the kept node values without a new forward pass.
+    #[pyo3(signature = (algorithm = "auto"))]
+    fn grad(&mut self, py: Python<'_>, algorithm: &str) -> PyResult<(Vec<f64>, Vec<f64>)> {
+        let algorithm: GradAlgorithm = algorithm.parse().map_err(PyValueError::new_err)?;
+        if self.grads.is_none() {
+            let mut grad_weights = PosNegWeights::new(self.weights.len());
+            let circuit = self.circuit.borrow(py);
+            let (circuit, buf): (&Circuit, &[f64]) = (&circuit, &self.buf);
+            py.allow_threads(|| circuit._backprop(algorithm, buf, 1., &mut grad_weights));
+            self.grads = Some(grad_weights.into_vecs());
+        }
+        Ok(self.grads.clone().unwrap())
+    }
+}
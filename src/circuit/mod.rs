@@ -4,8 +4,13 @@ use crate::circuit::rcircuit::{RCircuit, RNode};
 mod amc;
 pub mod circuit;
 mod compile;
+pub mod incremental;
+pub mod models;
 pub mod parser;
 pub mod rcircuit;
+pub mod tape;
+mod transform;
+pub mod validate;
 mod weights;
 mod tseitin;
 
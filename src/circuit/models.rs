@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+use pyo3::{pyclass, pymethods, Py, PyRef, PyRefMut, Python};
+use crate::algebra::BoolSemiring;
+use crate::circuit::amc::{amc, amc_update};
+use crate::circuit::circuit::{Circuit, Node};
+use crate::circuit::weights::PosNegWeights;
+
+/// Lazy iterator over the models of a circuit, projected on a list of variables.
+/// Models are yielded as lists of literals in the order of the variables, and are
+/// enumerated in lexicographic order where a negative literal comes before a positive one.
+/// E.g. on the variables [1, 2] the order is [-1, -2], [-1, 2], [1, -2], [1, 2].
+#[pyclass]
+pub struct ModelIterator {
+    circuit: Py<Circuit>,
+    search: ModelSearch,
+    limit: Option<usize>,
+}
+
+impl ModelIterator {
+    pub(crate) fn new(circuit: PyRef<'_, Circuit>, search: ModelSearch, limit: Option<usize>) -> Self {
+        ModelIterator { circuit: circuit.into(), search, limit }
+    }
+}
+
+/// Depth-first search over the projected variables, which only extends consistent
+/// assignments, so every leaf of the search is a distinct model.
+pub(crate) struct ModelSearch {
+    variables: Vec<u32>,
+    parents: Vec<Vec<usize>>,
+    leaves: HashMap<i32, Vec<usize>>,
+    // Consistency of every node with the current partial assignment, kept up to date incrementally
+    weights: PosNegWeights<BoolSemiring>,
+    buf: Vec<BoolSemiring>,
+    // Literals of the first variables, which are always consistent with the circuit
+    lits: Vec<i32>,
+    started: bool,
+}
+
+impl ModelSearch {
+    pub(crate) fn new(circuit: &Circuit, variables: Vec<u32>) -> Self {
+        let mut seen = HashSet::new();
+        let variables: Vec<u32> = variables.into_iter().filter(|&v| seen.insert(v)).collect();
+        let nb_vars = variables.iter().copied().max().unwrap_or(0).max(circuit.nb_vars()) as usize;
+        let weights = PosNegWeights::from_vecs(vec![true; nb_vars], vec![true; nb_vars]);
+        let mut leaves: HashMap<i32, Vec<usize>> = HashMap::new();
+        for (i, node) in circuit.nodes().enumerate() {
+            if let Node::Leaf(lit) = node {
+                leaves.entry(lit).or_default().push(i);
+            }
+        }
+        ModelSearch {
+            buf: amc::<BoolSemiring>(circuit, &weights),
+            parents: circuit.parents(),
+            variables,
+            leaves,
+            weights,
+            lits: vec![],
+            started: false,
+        }
+    }
+
+    /// Whether the circuit has a model extending the current literals.
+    fn is_consistent(&self) -> bool {
+        self.buf.last().is_some_and(|root| root.0)
+    }
+
+    /// Observes (or forgets) the literal by setting the weight of its negation to false (or true),
+    /// and only re-evaluates the ancestors of the affected leaves.
+    fn set(&mut self, circuit: &Circuit, lit: i32, observed: bool) {
+        self.weights.set(-lit, BoolSemiring(!observed));
+        let dirty = self.leaves.get(&-lit).into_iter().flatten().copied();
+        amc_update(circuit, &self.parents, &self.weights, &mut self.buf, dirty);
+    }
+
+    /// Moves to the next consistent assignment of the projected variables, if there is one.
+    fn advance(&mut self, circuit: &Circuit) -> bool {
+        if !self.started {
+            self.started = true;
+            if !self.is_consistent() {
+                return false;
+            }
+        } else {
+            // Backtracks to the last negative literal whose positive literal is consistent.
+            loop {
+                let Some(lit) = self.lits.pop() else {
+                    return false;
+                };
+                self.set(circuit, lit, false);
+                if lit < 0 {
+                    self.set(circuit, -lit, true);
+                    if self.is_consistent() {
+                        self.lits.push(-lit);
+                        break;
+                    }
+                    self.set(circuit, -lit, false);
+                }
+            }
+        }
+        // Some model extends the literals, so one of the literals of the next variable is consistent.
+        while let Some(&var) = self.variables.get(self.lits.len()) {
+            let var = var as i32;
+            self.set(circuit, -var, true);
+            if self.is_consistent() {
+                self.lits.push(-var);
+            } else {
+                self.set(circuit, -var, false);
+                self.set(circuit, var, true);
+                self.lits.push(var);
+            }
+        }
+        true
+    }
+}
+
+#[pymethods]
+impl ModelIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python<'_>) -> Option<Vec<i32>> {
+        if slf.limit == Some(0) {
+            return None;
+        }
+        let circuit = slf.circuit.clone_ref(py);
+        let circuit = circuit.borrow(py);
+        let (circuit, search): (&Circuit, &mut ModelSearch) = (&circuit, &mut slf.search);
+        if !py.allow_threads(|| search.advance(circuit)) {
+            slf.limit = Some(0);
+            return None;
+        }
+        slf.limit = slf.limit.map(|limit| limit - 1);
+        Some(slf.search.lits.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::circuit::{non_smooth, NodeKind};
+    use super::*;
+
+    fn models(circuit: &Circuit, variables: Vec<u32>) -> Vec<Vec<i32>> {
+        let mut search = ModelSearch::new(circuit, variables);
+        let mut models = vec![];
+        while search.advance(circuit) {
+            models.push(search.lits.clone());
+        }
+        models
+    }
+
+    #[test]
+    fn negative_literals_first() {
+        assert_eq!(models(&non_smooth(), vec![1, 2]), vec![vec![-1, 2], vec![1, -2], vec![1, 2]]);
+        assert_eq!(models(&non_smooth(), vec![2, 1]), vec![vec![-2, 1], vec![2, -1], vec![2, 1]]);
+    }
+
+    #[test]
+    fn projection_without_duplicates() {
+        assert_eq!(models(&non_smooth(), vec![2, 2]), vec![vec![-2], vec![2]]);
+        assert_eq!(models(&non_smooth(), vec![3]), vec![vec![-3], vec![3]]);
+        assert_eq!(models(&non_smooth(), vec![]), vec![Vec::<i32>::new()]);
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        let mut circuit = Circuit::new();
+        circuit.push(NodeKind::Or, &[]).unwrap();
+        assert!(models(&circuit, vec![1]).is_empty());
+        assert!(models(&Circuit::new(), vec![]).is_empty());
+    }
+}
@@ -1,112 +1,145 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io;
 use std::io::{BufRead, BufReader};
-use pyo3::pyfunction;
-use crate::circuit::circuit::{Circuit, Node};
+use pyo3::{pyfunction, Python};
+use crate::circuit::circuit::{Circuit, NodeKind};
 use crate::circuit::rcircuit::{RCircuit, RNode};
+use crate::error::{Error, Result};
 
-/// Parse a compiled d4 circuit from a file.
+/// Parse a compiled d4 circuit from a file, without holding the GIL.
 #[pyfunction]
-pub fn load_d4(filename: &str) -> io::Result<Circuit> {
+pub fn load_d4(py: Python, filename: &str) -> Result<Circuit> {
+    py.allow_threads(|| parse_d4(filename))
+}
+
+pub fn parse_d4(filename: &str) -> Result<Circuit> {
 
     let file = File::open(filename)?;
     let mut reader = BufReader::new(file);
-    let mut nodes: Vec<Node> = Vec::new();
+    let mut nodes = Circuit::new();
     let mut line = String::new();
+    let mut line_nb = 0;
     let mut node_map: Vec<Option<usize>> = vec![None]; // d4 node index -> circuit index
-    let mut lits_map: Vec<Option<usize>> = Vec::new(); // literal -> circuit index
-    let mut cnode_cache: HashMap<usize, Node> = HashMap::new(); // d4 node index -> node
+    let mut lits_map: HashMap<i32, usize> = HashMap::new(); // literal -> circuit index
+    let mut cnode_cache: HashMap<usize, (NodeKind, Vec<usize>)> = HashMap::new(); // d4 node index -> node and children
 
-    fn to_node_ix(node_map: &mut Vec<Option<usize>>, nodes: &mut Vec<Node>, cnode_cache: &mut HashMap<usize, Node>, d4_ix: usize) -> usize {
-        match node_map[d4_ix] {
-            Some(ix) => ix,
+    fn to_node_ix(node_map: &mut [Option<usize>], nodes: &mut Circuit, cnode_cache: &mut HashMap<usize, (NodeKind, Vec<usize>)>, d4_ix: usize) -> Result<Option<usize>> {
+        let Some(&ix) = node_map.get(d4_ix) else {
+            return Ok(None);
+        };
+        match ix {
+            Some(ix) => Ok(Some(ix)),
             None => {
                 // Child node must be ready, as it's getting used.
-                node_map[d4_ix] = Some(nodes.len());
-                nodes.push(cnode_cache.remove(&d4_ix).unwrap());
-                nodes.len()-1
+                let Some((kind, children)) = cnode_cache.remove(&d4_ix) else {
+                    return Ok(None);
+                };
+                let ix = nodes.push(kind, &children)?;
+                node_map[d4_ix] = Some(ix);
+                Ok(Some(ix))
             }
         }
     }
 
-    fn to_lit_ix(lits_map: &mut Vec<Option<usize>>, nodes: &mut Vec<Node>, lit: i32) -> usize {
-        let lit_ix = (2 * lit.abs() + (if lit > 0 {-2} else {-1})) as usize;
-        if lit_ix >= lits_map.len() {
-            lits_map.resize(lit_ix+1, None);
-        }
-        match lits_map[lit_ix] {
-            Some(ix) => ix,
+    fn to_lit_ix(lits_map: &mut HashMap<i32, usize>, nodes: &mut Circuit, lit: i32) -> Result<usize> {
+        match lits_map.get(&lit) {
+            Some(&ix) => Ok(ix),
             None => {
-                nodes.push(Node::Leaf(lit));
-                lits_map[lit_ix] = Some(nodes.len()-1);
-                nodes.len()-1
+                let ix = nodes.push(NodeKind::Leaf(lit), &[])?;
+                lits_map.insert(lit, ix);
+                Ok(ix)
             }
         }
     }
 
+    fn parse_err(line_nb: usize, msg: &str, line: &str) -> Error {
+        Error::Parse(format!("Line {}: {} in '{}'", line_nb, msg, line.trim()))
+    }
+
     while reader.read_line(&mut line)? != 0 {
-        if line.is_empty() {
-            line.clear();
-            continue;
-        }
-        let first_char = line.chars().nth(0).unwrap();
+        line_nb += 1;
+        let first_char = match line.trim().chars().next() {
+            Some(c) => c,
+            None => {
+                line.clear();
+                continue;
+            }
+        };
 
         if first_char == 'o' || first_char == 'a' || first_char == 'f' || first_char == 't' {
             // Introduction of a new node: add it to the cache.
-            let node_index = line.split_ascii_whitespace()
-                .nth(1).unwrap().parse().unwrap();
-            let node = match first_char {
-                'a' => Node::And(vec![]),
-                't' => Node::And(vec![]),
-                _ => Node::Or(vec![]),
+            let node_index: usize = line.split_ascii_whitespace()
+                .nth(1).and_then(|x| x.parse().ok())
+                .ok_or_else(|| parse_err(line_nb, "Invalid node index", &line))?;
+            if node_index != node_map.len() {
+                return Err(parse_err(line_nb, "Nodes must be numbered consecutively from 1", &line));
+            }
+            let kind = match first_char {
+                'a' => NodeKind::And,
+                't' => NodeKind::And,
+                _ => NodeKind::Or,
             };
-            cnode_cache.insert(node_index, node);
+            cnode_cache.insert(node_index, (kind, vec![]));
             node_map.push(None);
         } else {
             let mut iter = line.split_ascii_whitespace();
-            let parent_ix = iter.next().unwrap().parse::<usize>().unwrap();
-            let child_ix = iter.next().unwrap().parse::<usize>().unwrap();
+            let mut next_ix = || iter.next().and_then(|x| x.parse::<usize>().ok());
+            let (parent_ix, child_ix) = match (next_ix(), next_ix()) {
+                (Some(parent_ix), Some(child_ix)) => (parent_ix, child_ix),
+                _ => return Err(parse_err(line_nb, "Invalid edge", &line)),
+            };
             let lits: Vec<i32> = iter.take_while(|x| *x != "0")
-                .map(|x| x.parse::<i32>().unwrap()).collect();
+                .map(|x| x.parse::<i32>().ok().filter(|&lit| lit != 0 && lit != i32::MIN))
+                .collect::<Option<_>>()
+                .ok_or_else(|| parse_err(line_nb, "Invalid literal", &line))?;
 
-            let child_ix = to_node_ix(&mut node_map, &mut nodes, &mut cnode_cache, child_ix);
+            let child_ix = to_node_ix(&mut node_map, &mut nodes, &mut cnode_cache, child_ix)?
+                .ok_or_else(|| parse_err(line_nb, "Unknown child node", &line))?;
             let source_ix = if lits.is_empty() {
                 child_ix
             } else {
                 let mut lits_ix: Vec<usize> = lits.iter()
-                    .map(|lit| to_lit_ix(&mut lits_map, &mut nodes, *lit)).collect();
+                    .map(|lit| to_lit_ix(&mut lits_map, &mut nodes, *lit)).collect::<Result<_>>()?;
                 lits_ix.push(child_ix);
-                nodes.push(Node::And(lits_ix));
-                nodes.len()-1
+                nodes.push(NodeKind::And, &lits_ix)?
             };
-            cnode_cache.get_mut(&parent_ix).unwrap().add_child(source_ix);
+            cnode_cache.get_mut(&parent_ix)
+                .ok_or_else(|| parse_err(line_nb, "Unknown or already used parent node", &line))?
+                .1.push(source_ix);
         }
         line.clear();
     }
 
     // Finalize root node
-    nodes.push(cnode_cache.remove(&1).unwrap());
+    let (kind, children) = cnode_cache.remove(&1)
+        .ok_or_else(|| Error::Parse("Missing root node 1".to_string()))?;
+    nodes.push(kind, &children)?;
     if !cnode_cache.is_empty() {
-        panic!("Dangling nodes detected!");
+        let mut dangling: Vec<usize> = cnode_cache.into_keys().collect();
+        dangling.sort_unstable();
+        return Err(Error::Parse(format!("Dangling nodes detected: {:?}", dangling)));
     }
-    Ok(Circuit { nodes })
+    Ok(nodes)
 }
 
 
 
 #[pyfunction]
-pub fn load_dimacs(filename: &str) -> io::Result<RCircuit> {
+pub fn load_dimacs(filename: &str) -> Result<RCircuit> {
     let file = File::open(filename)?;
     let reader = BufReader::new(file);
     let mut clauses = Vec::new();
-    let lines = reader.lines()
-        .map(|l| l.unwrap())
-        .filter(|l| !l.starts_with("c") && !l.starts_with("p"));
 
-    for line in lines {
-        let clause = line.split_whitespace()
-            .map(|lit| lit.parse().unwrap())
+    for (line_nb, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with('c') || line.starts_with('p') {
+            continue;
+        }
+        let lits: Vec<i32> = line.split_whitespace()
+            .map(|lit| lit.parse().ok())
+            .collect::<Option<_>>()
+            .ok_or_else(|| Error::Parse(format!("Line {}: Invalid literal in '{}'", line_nb + 1, line.trim())))?;
+        let clause = lits.into_iter()
             .take_while(|lit| *lit != 0)
             .map(|lit| RNode::Val(lit).into())
             .collect();
@@ -115,3 +148,40 @@ pub fn load_dimacs(filename: &str) -> io::Result<RCircuit> {
 
     Ok(RNode::Prod(clauses).into())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    fn parse(name: &str, content: &str) -> Result<Circuit> {
+        let path = std::env::temp_dir().join(format!("kompyle_parser_{}.nnf", name));
+        File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+        let circuit = parse_d4(path.to_str().unwrap());
+        std::fs::remove_file(path).unwrap();
+        circuit
+    }
+
+    #[test]
+    fn parses_shared_literals() {
+        let circuit = parse("valid", "o 1 0\nt 2 0\n1 2 1 -2 0\n1 2 -1 0\n").unwrap();
+        // The true node 2, the leaves 1, -2 and -1, an And node for each edge, and the root
+        assert_eq!(circuit.nb_nodes(), 7);
+        assert_eq!(circuit.nb_vars(), 2);
+    }
+
+    #[test]
+    fn malformed_files_are_parse_errors() {
+        for (name, content) in [
+            ("index", "o x 0\n"),
+            ("numbering", "o 2 0\n"),
+            ("edge", "o 1 0\n1\n"),
+            ("literal", "o 1 0\nt 2 0\n1 2 -2147483648 0\n"),
+            ("child", "o 1 0\n1 3 0\n"),
+            ("root", ""),
+            ("dangling", "o 1 0\nt 2 0\n"),
+        ] {
+            assert!(matches!(parse(name, content), Err(Error::Parse(_))), "{}", name);
+        }
+    }
+}
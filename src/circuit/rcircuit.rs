@@ -1,10 +1,11 @@
 use std::rc::Rc;
-use pyo3::{pyclass, pymethods, PyResult};
+use pyo3::{pyclass, pymethods, PyResult, Python};
 use pyo3::basic::CompareOp;
 use pyo3::exceptions::PyRuntimeError;
 use crate::circuit::circuit::Circuit;
 use crate::circuit::compile::{compile, to_dimacs};
 use crate::circuit::tseitin::tseitin_transform;
+use crate::error::{Error, Result};
 
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -19,26 +20,15 @@ pub enum RNode {
 
 impl RNode {
     pub fn is_leaf(&self) -> bool {
-        match self {
-            RNode::Val(_) => true,
-            RNode::Zero => true,
-            RNode::One => true,
-            _ => false,
-        }
+        matches!(self, RNode::Val(_) | RNode::Zero | RNode::One)
     }
 
     pub fn is_true(&self) -> bool {
-        match self {
-            RNode::One => true,
-            _ => false,
-        }
+        matches!(self, RNode::One)
     }
 
     pub fn is_false(&self) -> bool {
-        match self {
-            RNode::Zero => true,
-            _ => false,
-        }
+        matches!(self, RNode::Zero)
     }
 
     pub fn is_clause(&self) -> bool {
@@ -71,7 +61,7 @@ impl RNode {
 
     pub fn nb_vars(&self) -> u32 {
         match self {
-            RNode::Val(v) => v.abs() as u32,
+            RNode::Val(v) => v.unsigned_abs(),
             RNode::Sum(vs) | RNode::Prod(vs) => vs.iter()
                 .map(|v| v.nb_vars()).max().unwrap_or(0),
             RNode::Neg(v) => v.nb_vars(),
@@ -81,29 +71,29 @@ impl RNode {
 
     pub fn children(&self) -> Vec<Rc<RNode>> {
         match self {
-            RNode::Sum(vs) => vs.iter().map(|v| Rc::clone(v)).collect(),
-            RNode::Prod(vs) => vs.iter().map(|v| Rc::clone(v)).collect(),
+            RNode::Sum(vs) => vs.iter().map(Rc::clone).collect(),
+            RNode::Prod(vs) => vs.iter().map(Rc::clone).collect(),
             RNode::Neg(v) => vec![Rc::clone(v)],
             _ => vec![],
         }
     }
 
-    pub fn value(&self) -> i32 {
+    pub fn value(&self) -> Result<i32> {
         match self {
-            RNode::Val(v) => *v,
-            _ => panic!("Not a value"),
+            RNode::Val(v) => Ok(*v),
+            _ => Err(Error::InvalidCircuit(format!("Not a literal: {:?}", self))),
         }
     }
 
-    pub fn condition(self: Rc<Self>, lits: &Vec<i32>) -> Rc<RNode> {
+    pub fn condition(self: Rc<Self>, lits: &[i32]) -> Rc<RNode> {
         condition(self, lits)
     }
 
     pub fn simplify(self: Rc<Self>) -> Rc<RNode> {
         // Simplify the circuit by removing redundant nodes.
         match *self {
-            RNode::Sum(ref vs) => simplify_or(&vs),
-            RNode::Prod(ref vs) => simplify_and(&vs),
+            RNode::Sum(ref vs) => simplify_or(vs),
+            RNode::Prod(ref vs) => simplify_and(vs),
             RNode::Neg(ref v) => RNode::Neg(v.clone().simplify()).into(),
             _ => self,
         }
@@ -112,7 +102,7 @@ impl RNode {
     pub fn negate(self: Rc<Self>) -> Rc<RNode> {
         match *self {
             RNode::Val(ref v) => RNode::Val(-v).into(),
-            RNode::Neg(ref v) => Rc::clone(&v),
+            RNode::Neg(ref v) => Rc::clone(v),
             RNode::One => RNode::Zero.into(),
             RNode::Zero => RNode::One.into(),
             _ => RNode::Neg(self.clone()).into(),
@@ -121,7 +111,7 @@ impl RNode {
 }
 
 
-fn simplify_or(children: &Vec<Rc<RNode>>) -> Rc<RNode> {
+fn simplify_or(children: &[Rc<RNode>]) -> Rc<RNode> {
     let children: Vec<Rc<RNode>> = children.iter()
         .map(|c| c.clone().simplify())
         .filter(|c| !c.is_false())
@@ -137,7 +127,7 @@ fn simplify_or(children: &Vec<Rc<RNode>>) -> Rc<RNode> {
 }
 
 
-fn simplify_and(children: &Vec<Rc<RNode>>) -> Rc<RNode> {
+fn simplify_and(children: &[Rc<RNode>]) -> Rc<RNode> {
     let children: Vec<Rc<RNode>> = children.iter()
         .map(|c| c.clone().simplify())
         .filter(|c| !c.is_true())
@@ -155,7 +145,7 @@ fn simplify_and(children: &Vec<Rc<RNode>>) -> Rc<RNode> {
 
 /// Condition the circuit on a literal.
 /// Returns a new circuit and a boolean indicating if the circuit was modified.
-fn condition(node: Rc<RNode>, lits: &Vec<i32>) -> Rc<RNode> {
+fn condition(node: Rc<RNode>, lits: &[i32]) -> Rc<RNode> {
     match *node {
         RNode::Val(ref v) if lits.contains(v)  => RNode::One.into(),
         RNode::Val(ref v) if lits.contains(&-*v) => RNode::Zero.into(),
@@ -181,6 +171,12 @@ impl From<RNode> for RCircuit {
     }
 }
 
+impl From<Rc<RNode>> for RCircuit {
+    fn from(node: Rc<RNode>) -> Self {
+        RCircuit { node }
+    }
+}
+
 
 #[pymethods]
 impl RCircuit {
@@ -206,7 +202,7 @@ impl RCircuit {
         RCircuit{node: self.node.clone().negate()}
     }
 
-    fn __int__(&self) -> i32 {
+    fn __int__(&self) -> Result<i32> {
         self.node.value()
     }
 
@@ -232,29 +228,29 @@ impl RCircuit {
     }
 
     fn condition(&self, lits: Vec<i32>) -> RCircuit {
-        RCircuit{node: self.node.clone().condition(&lits).into()}
+        RCircuit{node: self.node.clone().condition(&lits)}
     }
 
-    fn tseitin(&self, nb_vars: u32) -> RCircuit {
-        RCircuit{node: tseitin_transform(self.node.clone(), nb_vars)}
+    fn tseitin(&self, nb_vars: u32) -> Result<RCircuit> {
+        Ok(RCircuit{node: tseitin_transform(self.node.clone(), nb_vars)?})
     }
 
-    fn compile(&self, nb_vars: u32) -> PyResult<Circuit> {
-        Ok(compile(self.node.clone(), nb_vars, "d4".to_string())?)
+    fn compile(&self, py: Python, nb_vars: u32) -> PyResult<Circuit> {
+        compile(py, self.node.clone(), nb_vars, "d4".to_string())
     }
 
-    fn clauses(&self) -> Vec<Vec<i32>> {
+    fn clauses(&self) -> Result<Vec<Vec<i32>>> {
         let nb_vars = self.node.nb_vars();
-        self.tseitin(nb_vars).node.children().iter().map(|c| {
+        self.tseitin(nb_vars)?.node.children().iter().map(|c| {
             c.children().iter().map(|l| {l.value()}).collect()
         }).collect()
     }
 
-    fn to_dimacs(&self) -> PyResult<String> {
+    fn to_dimacs(&self) -> Result<String> {
         if self.node.is_cnf() {
-            Ok(to_dimacs(self.node.clone()))
+            to_dimacs(self.node.clone())
         } else {
-            Err(PyRuntimeError::new_err("Not a CNF"))
+            Err(Error::InvalidCircuit("Not a CNF".to_string()))
         }
     }
 }
@@ -0,0 +1,29 @@
+use pyo3::{pyclass, pymethods};
+use numpy::ndarray::Array1;
+use crate::algebra::{LogNdarraySemiring, NdarraySemiring};
+
+/// Node values of a batched forward pass, which can be backpropagated
+/// with different upstream gradients without recomputing the forward pass.
+#[pyclass]
+pub struct Tape {
+    pub(crate) buf: TapeBuffer,
+    pub(crate) nb_vars: usize,
+    pub(crate) batch_size: usize,
+}
+
+pub(crate) enum TapeBuffer {
+    Real(Vec<NdarraySemiring>),
+    // The log-weights are kept to take the gradient with respect to them.
+    Log(Vec<LogNdarraySemiring>, Vec<Array1<f32>>, Vec<Array1<f32>>),
+}
+
+#[pymethods]
+impl Tape {
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn is_log(&self) -> bool {
+        matches!(self.buf, TapeBuffer::Log(..))
+    }
+}
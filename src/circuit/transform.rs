@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use crate::circuit::circuit::{Circuit, Node, NodeKind};
+use crate::error::Result;
+
+/// Copies a circuit node by node, sharing leaves and the `(x ∨ ¬x)` gadgets used for smoothing.
+struct Builder {
+    circuit: Circuit,
+    leaves: HashMap<i32, usize>,
+    gadgets: HashMap<u32, usize>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Builder { circuit: Circuit::new(), leaves: HashMap::new(), gadgets: HashMap::new() }
+    }
+
+    fn leaf(&mut self, lit: i32) -> Result<usize> {
+        if let Some(&i) = self.leaves.get(&lit) {
+            return Ok(i);
+        }
+        let i = self.circuit.push(NodeKind::Leaf(lit), &[])?;
+        self.leaves.insert(lit, i);
+        Ok(i)
+    }
+
+    fn gadget(&mut self, var: u32) -> Result<usize> {
+        if let Some(&i) = self.gadgets.get(&var) {
+            return Ok(i);
+        }
+        let children = [self.leaf(var as i32)?, self.leaf(-(var as i32))?];
+        let i = self.circuit.push(NodeKind::Or, &children)?;
+        self.gadgets.insert(var, i);
+        Ok(i)
+    }
+
+    /// Conjoins the node with the gadgets of the variables, if there are any.
+    fn pad(&mut self, i: usize, vars: impl Iterator<Item = u32>) -> Result<usize> {
+        let mut children = vec![i];
+        for v in vars {
+            children.push(self.gadget(v)?);
+        }
+        match children.len() {
+            1 => Ok(i),
+            _ => self.circuit.push(NodeKind::And, &children),
+        }
+    }
+}
+
+/// Variables of the sorted list `a` which are not in the sorted list `b`.
+fn sorted_difference<'a>(a: &'a [u32], b: &'a [u32]) -> impl Iterator<Item = u32> + 'a {
+    let mut j = 0;
+    a.iter().copied().filter(move |&v| {
+        while j < b.len() && b[j] < v {
+            j += 1;
+        }
+        j == b.len() || b[j] != v
+    })
+}
+
+/// Equivalent circuit in which the children of every Or node mention the same variables.
+/// When `nb_vars` is given, the root also mentions all the variables from 1 to `nb_vars`.
+pub(crate) fn smooth(circuit: &Circuit, nb_vars: Option<u32>) -> Result<Circuit> {
+    let scopes = circuit.scopes();
+    let mut builder = Builder::new();
+    let mut map: Vec<usize> = Vec::with_capacity(circuit.nb_nodes());
+    for (i, node) in circuit.nodes().enumerate() {
+        let new = match node {
+            Node::Or(children) => {
+                let children: Vec<usize> = children.iter().map(|&j| {
+                    let missing = sorted_difference(&scopes[i], &scopes[j as usize]);
+                    builder.pad(map[j as usize], missing)
+                }).collect::<Result<_>>()?;
+                builder.circuit.push(NodeKind::Or, &children)?
+            },
+            Node::And(children) => {
+                let children: Vec<usize> = children.iter().map(|&j| map[j as usize]).collect();
+                builder.circuit.push(NodeKind::And, &children)?
+            },
+            Node::Leaf(lit) => builder.leaf(lit)?,
+        };
+        map.push(new);
+    }
+    let Some(&root) = map.last() else {
+        return Ok(builder.circuit);
+    };
+    let root = match nb_vars {
+        Some(nb_vars) => {
+            let all: Vec<u32> = (1..=nb_vars).collect();
+            let missing: Vec<u32> = sorted_difference(&all, &scopes[scopes.len() - 1]).collect();
+            builder.pad(root, missing.into_iter())?
+        },
+        None => root,
+    };
+    // Sharing leaves and gadgets can leave the root before other nodes, but it must be last.
+    if root + 1 != builder.circuit.nb_nodes() {
+        builder.circuit.push(NodeKind::And, &[root])?;
+    }
+    Ok(builder.circuit)
+}
+
+/// A node after constant folding, which is either a constant or a node of the new circuit.
+#[derive(Clone, Copy)]
+enum Folded {
+    Const(bool),
+    Node(usize),
+}
+
+/// Equivalent circuit without duplicate nodes, constants below the root, unary gates
+/// or nodes unreachable from the root. A constant root becomes an empty Or or And.
+pub(crate) fn minimize(circuit: &Circuit) -> Result<Circuit> {
+    simplify(circuit, |_| None)
+}
+
+/// Circuit conditioned on the literals, which must not contain both a literal and its negation.
+pub(crate) fn condition(circuit: &Circuit, lits: &[i32]) -> Result<Circuit> {
+    let lits: HashSet<i32> = lits.iter().copied().collect();
+    simplify(circuit, |lit| match (lits.contains(&lit), lits.contains(&-lit)) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    })
+}
+
+/// Existential quantification of the variables, which replaces their literals by true.
+/// The result stays decomposable, but Or nodes which mentioned one of the variables may
+/// no longer be deterministic, so also returns whether there were none.
+pub(crate) fn forget(circuit: &Circuit, vars: &[u32]) -> Result<(Circuit, bool)> {
+    let vars: HashSet<u32> = vars.iter().copied().collect();
+    let scopes = circuit.scopes();
+    let deterministic = circuit.nodes().zip(&scopes).all(|(node, scope)| match node {
+        Node::Or(children) if children.len() > 1 => !scope.iter().any(|v| vars.contains(v)),
+        _ => true,
+    });
+    Ok((simplify(circuit, |lit| vars.contains(&lit.unsigned_abs()).then_some(true))?, deterministic))
+}
+
+/// Minimizes the circuit after replacing the leaves for which `fixed` returns a value by constants.
+fn simplify(circuit: &Circuit, fixed: impl Fn(i32) -> Option<bool>) -> Result<Circuit> {
+    let mut folded = Circuit::new();
+    let mut unique: HashMap<(NodeKind, Vec<usize>), usize> = HashMap::new();
+    let mut map: Vec<Folded> = Vec::with_capacity(circuit.nb_nodes());
+    for node in circuit.nodes() {
+        let (kind, children) = match node {
+            Node::Or(children) => (NodeKind::Or, children),
+            Node::And(children) => (NodeKind::And, children),
+            Node::Leaf(lit) => (NodeKind::Leaf(lit), &[][..]),
+        };
+        // True absorbs Or nodes and is neutral for And nodes, and conversely for false.
+        let absorbing = kind == NodeKind::Or;
+        let mut new_children = Vec::with_capacity(children.len());
+        let mut absorbed = false;
+        for &j in children {
+            match map[j as usize] {
+                Folded::Const(value) if value == absorbing => absorbed = true,
+                Folded::Const(_) => {},
+                Folded::Node(j) => new_children.push(j),
+            }
+        }
+        new_children.sort_unstable();
+        let new = match (kind, new_children.len()) {
+            (NodeKind::Leaf(lit), _) => fixed(lit).map(Folded::Const),
+            _ if absorbed => Some(Folded::Const(absorbing)),
+            (_, 0) => Some(Folded::Const(!absorbing)),
+            (_, 1) => Some(Folded::Node(new_children[0])),
+            _ => None,
+        };
+        let new = match new {
+            Some(new) => new,
+            None => match unique.entry((kind, new_children)) {
+                Entry::Occupied(entry) => Folded::Node(*entry.get()),
+                Entry::Vacant(entry) => {
+                    let i = folded.push(kind, &entry.key().1)?;
+                    Folded::Node(*entry.insert(i))
+                },
+            },
+        };
+        map.push(new);
+    }
+    let mut minimized = Circuit::new();
+    match map.last() {
+        None => {},
+        Some(Folded::Const(true)) => { minimized.push(NodeKind::And, &[])?; },
+        Some(Folded::Const(false)) => { minimized.push(NodeKind::Or, &[])?; },
+        Some(&Folded::Node(root)) => {
+            // Children come before their parents, so the reachable nodes are already sorted with the root last.
+            let mut reachable = vec![false; root + 1];
+            reachable[root] = true;
+            for i in (0..=root).rev() {
+                if let (true, Node::Or(children) | Node::And(children)) = (reachable[i], folded.node(i)) {
+                    children.iter().for_each(|&j| reachable[j as usize] = true);
+                }
+            }
+            let mut new_index = vec![usize::MAX; root + 1];
+            for i in (0..=root).filter(|&i| reachable[i]) {
+                new_index[i] = match folded.node(i) {
+                    Node::Or(children) => minimized.push(NodeKind::Or, &children.iter().map(|&j| new_index[j as usize]).collect::<Vec<_>>())?,
+                    Node::And(children) => minimized.push(NodeKind::And, &children.iter().map(|&j| new_index[j as usize]).collect::<Vec<_>>())?,
+                    Node::Leaf(lit) => minimized.push(NodeKind::Leaf(lit), &[])?,
+                };
+            }
+        },
+    }
+    Ok(minimized)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algebra::BoolSemiring;
+    use crate::circuit::amc::amc1;
+    use crate::circuit::circuit::non_smooth;
+    use crate::circuit::weights::PosNegWeights;
+    use super::*;
+
+    /// Truth table of the circuit over the variables 1 to `nb_vars`.
+    fn truth_table(circuit: &Circuit, nb_vars: u32) -> Vec<bool> {
+        (0..1u32 << nb_vars).map(|a| {
+            let value = |v: u32| a >> (v - 1) & 1 == 1;
+            let weights: PosNegWeights<BoolSemiring> = PosNegWeights::from_vecs(
+                (1..=nb_vars).map(value).collect(),
+                (1..=nb_vars).map(|v| !value(v)).collect(),
+            );
+            amc1::<BoolSemiring>(circuit, &weights).into()
+        }).collect()
+    }
+
+    #[test]
+    fn smoothing_preserves_the_models() {
+        let circuit = non_smooth();
+        let smoothed = smooth(&circuit, Some(3)).unwrap();
+        assert_eq!(truth_table(&smoothed, 3), truth_table(&circuit, 3));
+        assert_eq!(smoothed.nb_vars(), 3);
+        let scopes = smoothed.scopes();
+        assert!(smoothed.nodes().enumerate().all(|(i, node)| match node {
+            Node::Or(children) => children.iter().all(|&j| scopes[j as usize] == scopes[i]),
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn smoothing_shares_the_gadgets() {
+        // (x1 ∨ x2) ∨ (x1 ∨ x2) needs the gadgets of x2 and x1 once each.
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+        let a = circuit.push(NodeKind::Or, &[x1, x2]).unwrap();
+        let b = circuit.push(NodeKind::Or, &[x1, x2]).unwrap();
+        circuit.push(NodeKind::Or, &[a, b]).unwrap();
+        // 4 leaves, 2 gadgets, the 2 copied Or nodes with their 2 padded children, and the root
+        assert_eq!(smooth(&circuit, None).unwrap().nb_nodes(), 4 + 2 + 2 * 3 + 1);
+    }
+
+    #[test]
+    fn minimization_folds_and_shares() {
+        // (x1 ∧ true) ∨ ((x1 ∧ x2) ∨ (x1 ∧ x2) ∨ false), plus an unreachable leaf
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+        let t = circuit.push(NodeKind::And, &[]).unwrap();
+        let f = circuit.push(NodeKind::Or, &[]).unwrap();
+        circuit.push(NodeKind::Leaf(3), &[]).unwrap();
+        let a = circuit.push(NodeKind::And, &[x1, t]).unwrap();
+        let b = circuit.push(NodeKind::And, &[x1, x2]).unwrap();
+        let c = circuit.push(NodeKind::And, &[x2, x1]).unwrap();
+        let d = circuit.push(NodeKind::Or, &[b, c, f]).unwrap();
+        circuit.push(NodeKind::Or, &[a, d]).unwrap();
+        let minimized = minimize(&circuit).unwrap();
+        assert_eq!(truth_table(&minimized, 3), truth_table(&circuit, 3));
+        // x1, x2, x1 ∧ x2, the Or of the shared And node, and the root
+        assert_eq!(minimized.nb_nodes(), 5);
+    }
+
+    #[test]
+    fn minimization_of_constants() {
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let t = circuit.push(NodeKind::And, &[]).unwrap();
+        circuit.push(NodeKind::Or, &[x1, t]).unwrap();
+        let minimized = minimize(&circuit).unwrap();
+        assert_eq!(minimized.nb_nodes(), 1);
+        assert!(matches!(minimized.node(0), Node::And(children) if children.is_empty()));
+        assert_eq!(minimize(&Circuit::new()).unwrap().nb_nodes(), 0);
+    }
+
+    #[test]
+    fn conditioning_fixes_the_literals() {
+        let circuit = non_smooth();
+        let table = truth_table(&circuit, 2);
+        let conditioned = condition(&circuit, &[-1]).unwrap();
+        // Only x2 is left.
+        assert_eq!(conditioned.nb_nodes(), 1);
+        assert_eq!(truth_table(&conditioned, 2), vec![table[0], table[0], table[2], table[2]]);
+        let conditioned = condition(&circuit, &[1]).unwrap();
+        assert!(matches!(conditioned.node(0), Node::And(children) if children.is_empty()));
+    }
+
+    #[test]
+    fn forgetting_projects_the_models() {
+        // (x1 ∧ x2) ∨ (¬x1 ∧ ¬x2)
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+        let not_x1 = circuit.push(NodeKind::Leaf(-1), &[]).unwrap();
+        let not_x2 = circuit.push(NodeKind::Leaf(-2), &[]).unwrap();
+        let a = circuit.push(NodeKind::And, &[x1, x2]).unwrap();
+        let b = circuit.push(NodeKind::And, &[not_x1, not_x2]).unwrap();
+        circuit.push(NodeKind::Or, &[a, b]).unwrap();
+        let (forgotten, deterministic) = forget(&circuit, &[1]).unwrap();
+        assert_eq!(truth_table(&forgotten, 2), vec![true; 4]);
+        assert!(!deterministic);
+        let (forgotten, deterministic) = forget(&circuit, &[3]).unwrap();
+        assert_eq!(truth_table(&forgotten, 2), truth_table(&circuit, 2));
+        assert!(deterministic);
+    }
+}
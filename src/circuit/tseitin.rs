@@ -1,17 +1,18 @@
 use std::rc::Rc;
 use std::iter::once;
 use crate::circuit::rcircuit::RNode;
+use crate::error::{Error, Result};
 
 
 type Clause = Vec<i32>;
 
 /// Tseitin transformation
 /// See https://en.wikipedia.org/wiki/Tseytin_transformation
-fn _цейтин(node: &RNode, next_var: &mut i32, clauses: &mut Vec<Clause>) -> i32 {
-    match node {
+fn _цейтин(node: &RNode, next_var: &mut i32, clauses: &mut Vec<Clause>) -> Result<i32> {
+    Ok(match node {
         RNode::Sum(children) => {
             let vars: Vec<i32> = children.iter()
-                .map(|child| _цейтин(child, next_var, clauses)).collect();
+                .map(|child| _цейтин(child, next_var, clauses)).collect::<Result<_>>()?;
             *next_var += 1;
             let new_var = *next_var;
             for var in &vars {
@@ -24,7 +25,7 @@ fn _цейтин(node: &RNode, next_var: &mut i32, clauses: &mut Vec<Clause>) ->
         }
         RNode::Prod(children) => {
             let vars: Vec<i32> = children.iter()
-                .map(|child| _цейтин(child, next_var, clauses)).collect();
+                .map(|child| _цейтин(child, next_var, clauses)).collect::<Result<_>>()?;
             *next_var += 1;
             let new_var = *next_var;
             for var in &vars {
@@ -36,7 +37,7 @@ fn _цейтин(node: &RNode, next_var: &mut i32, clauses: &mut Vec<Clause>) ->
             new_var
         }
         RNode::Neg(child) => {
-            let child_var: i32 = _цейтин(child, next_var, clauses);
+            let child_var: i32 = _цейтин(child, next_var, clauses)?;
             *next_var += 1;
             let new_var = *next_var;
             clauses.push(vec![new_var, child_var]);
@@ -44,22 +45,22 @@ fn _цейтин(node: &RNode, next_var: &mut i32, clauses: &mut Vec<Clause>) ->
             new_var
         }
         RNode::Val(value) => *value,
-        _ => panic!("Unexpected node"),
-    }
+        _ => return Err(Error::InvalidCircuit("Can't Tseitin transform true/false nodes, simplify the circuit first".to_string())),
+    })
 }
 
 
-pub fn tseitin_transform(node: Rc<RNode>, nb_vars: u32) -> Rc<RNode> {
+pub fn tseitin_transform(node: Rc<RNode>, nb_vars: u32) -> Result<Rc<RNode>> {
     if node.is_cnf() {
-        return node;
+        return Ok(node);
     }
 
     let mut next_var = nb_vars as i32;
     let mut clauses = vec![];
-    let new_var = _цейтин(&node, &mut next_var, &mut clauses);
+    let new_var = _цейтин(&node, &mut next_var, &mut clauses)?;
     clauses.push(vec![new_var]);
     let clauses = clauses.iter()
         .map(|c| RNode::Sum(c.iter().map(|v| RNode::Val(*v).into()).collect()).into())
         .collect();
-    RNode::Prod(clauses).into()
+    Ok(RNode::Prod(clauses).into())
 }
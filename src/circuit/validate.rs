@@ -0,0 +1,202 @@
+use rand::Rng;
+use pyo3::{pyclass, pymethods};
+use crate::circuit::amc::{amc, amc_sample};
+use crate::circuit::circuit::{Circuit, Node};
+use crate::circuit::weights::PosOnlyWeights;
+
+/// Structural properties of a circuit, as found by `Circuit.validate`.
+/// Nodes are identified by their index in the circuit, with the root last.
+/// The nodes are always in topological order, as a node can only be added after its children.
+#[pyclass]
+pub struct ValidationReport {
+    /// Nodes which are not descendants of the root
+    #[pyo3(get)]
+    unreachable: Vec<usize>,
+    /// And nodes whose children share a variable
+    #[pyo3(get)]
+    non_decomposable: Vec<usize>,
+    /// Or nodes whose children mention different variables
+    #[pyo3(get)]
+    non_smooth: Vec<usize>,
+    /// Or nodes with several children that are true under the same assignment
+    #[pyo3(get)]
+    non_deterministic: Vec<usize>,
+    /// Whether determinism was checked on all assignments, or only on samples
+    #[pyo3(get)]
+    determinism_exact: bool,
+}
+
+#[pymethods]
+impl ValidationReport {
+    fn is_decomposable(&self) -> bool {
+        self.non_decomposable.is_empty()
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.non_smooth.is_empty()
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.non_deterministic.is_empty()
+    }
+
+    /// Whether the circuit is a d-DNNF, i.e. decomposable and deterministic, which is what the
+    /// model counting assumes. Smoothness and unreachable nodes are reported but not required,
+    /// see `is_smooth` and `unreachable`.
+    fn is_valid(&self) -> bool {
+        self.is_decomposable() && self.is_deterministic()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ValidationReport(unreachable={:?}, non_decomposable={:?}, non_smooth={:?}, non_deterministic={:?}, determinism_exact={})",
+            self.unreachable, self.non_decomposable, self.non_smooth, self.non_deterministic,
+            if self.determinism_exact { "True" } else { "False" },
+        )
+    }
+}
+
+/// Checks the structure of the circuit. Determinism is checked on all assignments when
+/// there are at most `exact_vars` variables, and otherwise on `nb_samples` assignments
+/// drawn from the circuit with random weights.
+pub(crate) fn validate<R: Rng>(circuit: &Circuit, exact_vars: usize, nb_samples: usize, rng: &mut R) -> ValidationReport {
+    let nb_nodes = circuit.nb_nodes();
+    let mut reachable = vec![false; nb_nodes];
+    if let Some(root) = reachable.last_mut() {
+        *root = true;
+    }
+    for (i, node) in circuit.nodes().enumerate().rev() {
+        if let Node::Or(children) | Node::And(children) = node {
+            if reachable[i] {
+                children.iter().for_each(|&j| reachable[j as usize] = true);
+            }
+        }
+    }
+    let unreachable = (0..nb_nodes).filter(|&i| !reachable[i]).collect();
+
+    // And nodes must have disjoint children scopes, Or nodes equal ones.
+    let mut non_decomposable = vec![];
+    let mut non_smooth = vec![];
+    let scopes = circuit.scopes();
+    let scope_len = |j: &u32| scopes.get(*j as usize).map_or(0, |s| s.len());
+    for (i, node) in circuit.nodes().enumerate() {
+        match node {
+            Node::And(children) if children.iter().map(scope_len).sum::<usize>() != scopes[i].len() => non_decomposable.push(i),
+            Node::Or(children) if children.iter().any(|j| scope_len(j) != scopes[i].len()) => non_smooth.push(i),
+            _ => {},
+        }
+    }
+    drop(scopes);
+
+    // Every Or node accumulates the lanes in which several of its children are true.
+    let nb_vars = circuit.nb_vars() as usize;
+    let mut overlaps: Vec<u64> = vec![0; nb_nodes];
+    let determinism_exact = nb_vars <= exact_vars.min(63);
+    if determinism_exact {
+        // Assignment number w * 64 + lane sets variable k to bit k of that number.
+        for w in 0..(1u64 << nb_vars.saturating_sub(6)) {
+            let var_masks: Vec<u64> = (0..nb_vars).map(|k| match k {
+                0..=5 => LANE_PATTERNS[k],
+                _ if w >> (k - 6) & 1 == 1 => !0,
+                _ => 0,
+            }).collect();
+            find_overlaps(circuit, &var_masks, &mut overlaps);
+        }
+    } else {
+        for _ in 0..nb_samples.div_ceil(64) {
+            let probs: Vec<f64> = (0..nb_vars).map(|_| rng.gen::<f64>()).collect();
+            let buf = amc::<f64>(circuit, &PosOnlyWeights::from_vec(probs.clone()));
+            let mut var_masks: Vec<u64> = vec![0; nb_vars];
+            for lane in 0..64 {
+                let sample = amc_sample(circuit, &buf, nb_vars, rng);
+                for (k, value) in sample.into_iter().enumerate() {
+                    if value.unwrap_or_else(|| rng.gen::<f64>() < probs[k]) {
+                        var_masks[k] |= 1 << lane;
+                    }
+                }
+            }
+            find_overlaps(circuit, &var_masks, &mut overlaps);
+        }
+    }
+    let non_deterministic = (0..nb_nodes).filter(|&i| overlaps[i] != 0).collect();
+
+    ValidationReport { unreachable, non_decomposable, non_smooth, non_deterministic, determinism_exact }
+}
+
+/// Lanes in which variables 1 to 6 are true, when the lane number enumerates their assignments.
+const LANE_PATTERNS: [u64; 6] = [
+    0xAAAA_AAAA_AAAA_AAAA,
+    0xCCCC_CCCC_CCCC_CCCC,
+    0xF0F0_F0F0_F0F0_F0F0,
+    0xFF00_FF00_FF00_FF00,
+    0xFFFF_0000_FFFF_0000,
+    0xFFFF_FFFF_0000_0000,
+];
+
+/// Evaluates 64 assignments at once, given as the lanes in which each variable is true,
+/// and adds the lanes in which an Or node has several true children to its overlaps.
+fn find_overlaps(circuit: &Circuit, var_masks: &[u64], overlaps: &mut [u64]) {
+    let mut buf: Vec<u64> = vec![0; circuit.nb_nodes()];
+    for (i, node) in circuit.nodes().enumerate() {
+        buf[i] = match node {
+            Node::Or(children) => {
+                let mut seen = 0;
+                for &j in children {
+                    overlaps[i] |= seen & buf[j as usize];
+                    seen |= buf[j as usize];
+                }
+                seen
+            },
+            Node::And(children) => children.iter().fold(!0, |acc, &j| acc & buf[j as usize]),
+            Node::Leaf(lit) => {
+                let mask = var_masks[(lit.unsigned_abs() - 1) as usize];
+                if lit > 0 { mask } else { !mask }
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::circuit::circuit::{non_smooth, NodeKind};
+    use super::*;
+
+    /// (x1 ∧ x1) ∨ x2, plus an unreachable leaf ¬x2 before the root.
+    fn circuit() -> Circuit {
+        let mut circuit = Circuit::new();
+        let x1 = circuit.push(NodeKind::Leaf(1), &[]).unwrap();
+        let x2 = circuit.push(NodeKind::Leaf(2), &[]).unwrap();
+        let and = circuit.push(NodeKind::And, &[x1, x1]).unwrap();
+        circuit.push(NodeKind::Leaf(-2), &[]).unwrap();
+        circuit.push(NodeKind::Or, &[and, x2]).unwrap();
+        circuit
+    }
+
+    #[test]
+    fn reports_every_property() {
+        let report = validate(&circuit(), 16, 0, &mut StdRng::seed_from_u64(0));
+        assert_eq!(report.unreachable, vec![3]);
+        assert_eq!(report.non_decomposable, vec![2]);
+        assert_eq!(report.non_smooth, vec![4]);
+        assert_eq!(report.non_deterministic, vec![4]);
+        assert!(report.determinism_exact);
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn sampled_determinism() {
+        let report = validate(&circuit(), 0, 64, &mut StdRng::seed_from_u64(0));
+        assert!(!report.determinism_exact);
+        assert_eq!(report.non_deterministic, vec![4]);
+    }
+
+    #[test]
+    fn smoothness_is_not_required() {
+        let report = validate(&non_smooth(), 16, 0, &mut StdRng::seed_from_u64(0));
+        assert_eq!(report.non_smooth, vec![4]);
+        assert!(report.unreachable.is_empty());
+        assert!(report.is_valid());
+    }
+}
@@ -1,9 +1,11 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
+use std::marker::PhantomData;
 use crate::algebra::{Semiring, Ring};
 
 // Algebraic Weights (or Labels) on the literals of a propositional formula.
 pub trait Weights<T: Semiring> {
-    fn val(&self, lit: i32) -> Option<Cow<T>>;
+    fn val(&self, lit: i32) -> Option<Cow<'_, T>>;
     fn len(&self) -> usize;
 
     fn add(&mut self, lit: i32, val: &T);
@@ -27,20 +29,39 @@ impl<T: Semiring> PosNegWeights<T> {
             }
     }
 
+    /// Sets the weight of the literal. The weights grow to its variable if needed, where
+    /// the new literals get weight one, as literals without weights have in `amc`.
+    pub fn set(&mut self, lit: i32, val: T) {
+        let lit_ix = (lit.abs() - 1) as usize;
+        self.grow(lit_ix + 1, T::one);
+        if lit > 0 {
+            self.pos_weights[lit_ix] = val;
+        } else {
+            self.neg_weights[lit_ix] = val;
+        }
+    }
+
     pub fn into_vecs<G: From<T>>(self) -> (Vec<G>, Vec<G>) {
         (self.pos_weights.into_iter().map(|x| x.into()).collect(),
         self.neg_weights.into_iter().map(|x| x.into()).collect())
     }
 
+    fn grow(&mut self, len: usize, fill: fn() -> T) {
+        for weights in [&mut self.pos_weights, &mut self.neg_weights] {
+            if weights.len() < len {
+                weights.resize_with(len, fill);
+            }
+        }
+    }
 
 }
 
 impl<T: Semiring> Weights<T> for PosNegWeights<T>
 {
-    fn val(&self, lit: i32) -> Option<Cow<T>> {
+    fn val(&self, lit: i32) -> Option<Cow<'_, T>> {
         let lit_ix = (lit.abs() - 1) as usize;
         let weights = if lit > 0 {&self.pos_weights} else {&self.neg_weights};
-        weights.get(lit_ix).map(|x| Cow::Borrowed(x))
+        weights.get(lit_ix).map(Cow::Borrowed)
     }
 
     fn len(&self) -> usize {
@@ -49,10 +70,11 @@ impl<T: Semiring> Weights<T> for PosNegWeights<T>
 
     fn add(&mut self, lit: i32, val: &T) {
         let lit_ix = (lit.abs() - 1) as usize;
+        self.grow(lit_ix + 1, T::zero);
         if lit > 0 {
             self.pos_weights[lit_ix] = self.pos_weights[lit_ix].clone() + val;
         } else {
-            self.neg_weights[lit_ix] = self.pos_weights[lit_ix].clone() + val;
+            self.neg_weights[lit_ix] = self.neg_weights[lit_ix].clone() + val;
         }
     }
 }
@@ -77,11 +99,11 @@ impl <T: Ring> PosOnlyWeights<T> {
 
 impl<T: Ring> Weights<T> for PosOnlyWeights<T>
 {
-    fn val(&self, lit: i32) -> Option<Cow<T>> {
+    fn val(&self, lit: i32) -> Option<Cow<'_, T>> {
         let lit_ix = (lit.abs() - 1) as usize;
         let val = self.weights.get(lit_ix);
         if lit > 0 {
-            val.map(|x| Cow::Borrowed(x))
+            val.map(Cow::Borrowed)
         } else {
             val.map(|x| Cow::Owned(x.negate()))
         }
@@ -93,10 +115,80 @@ impl<T: Ring> Weights<T> for PosOnlyWeights<T>
 
     fn add(&mut self, lit: i32, val: &T) {
         let lit_ix = (lit.abs() - 1) as usize;
+        if self.weights.len() <= lit_ix {
+            self.weights.resize(lit_ix + 1, T::zero());
+        }
         if lit > 0 {
             self.weights[lit_ix] = self.weights[lit_ix].clone() + val;
         } else {
             self.weights[lit_ix] = self.weights[lit_ix].clone() - val;
         }
     }
-}
\ No newline at end of file
+}
+
+// Weights clamped to evidence: the literals that contradict it get weight zero.
+pub struct EvidenceWeights<T: Semiring, W: Weights<T>> {
+    weights: W,
+    excluded: HashSet<i32>,
+    semiring: PhantomData<T>,
+}
+
+impl<T: Semiring, W: Weights<T>> EvidenceWeights<T, W> {
+    pub fn new(weights: W) -> Self {
+        EvidenceWeights { weights, excluded: HashSet::new(), semiring: PhantomData }
+    }
+
+    pub fn observe(&mut self, lits: &[i32]) {
+        self.excluded.extend(lits.iter().map(|lit| -lit));
+    }
+}
+
+impl<T: Semiring, W: Weights<T>> Weights<T> for EvidenceWeights<T, W>
+{
+    fn val(&self, lit: i32) -> Option<Cow<'_, T>> {
+        if self.excluded.contains(&lit) {
+            Some(Cow::Owned(T::zero()))
+        } else {
+            self.weights.val(lit)
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    fn add(&mut self, lit: i32, val: &T) {
+        self.weights.add(lit, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_to_negative_literals() {
+        let mut weights = PosNegWeights::<f64>::from_vecs(vec![1., 2.], vec![3., 4.]);
+        weights.add(-2, &10.);
+        weights.add(1, &10.);
+        assert_eq!(weights.into_vecs::<f64>(), (vec![11., 2.], vec![3., 14.]));
+    }
+
+    #[test]
+    fn set_and_add_beyond_the_weights() {
+        let mut weights: PosNegWeights<f64> = PosNegWeights::from_vecs(vec![0.5], vec![0.5]);
+        weights.set(-3, 0.25);
+        assert_eq!(weights.len(), 3);
+        assert_eq!(*weights.val(3).unwrap(), 1.);
+        assert_eq!(*weights.val(-3).unwrap(), 0.25);
+        weights.add(4, &2.);
+        assert_eq!(weights.into_vecs::<f64>(), (vec![0.5, 1., 1., 2.], vec![0.5, 1., 0.25, 0.]));
+    }
+
+    #[test]
+    fn set_with_unequal_lengths() {
+        let mut weights: PosNegWeights<f64> = PosNegWeights::from_vecs(vec![0.5, 0.5], vec![0.5]);
+        weights.set(-2, 0.25);
+        assert_eq!(weights.into_vecs::<f64>(), (vec![0.5, 0.5], vec![0.5, 0.25]));
+    }
+}
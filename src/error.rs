@@ -0,0 +1,59 @@
+// create_exception! of pyo3 0.18 uses cfgs which newer compilers don't know.
+#![allow(unexpected_cfgs)]
+
+use std::io;
+use num_traits::Float;
+use pyo3::{create_exception, PyErr, Python};
+use pyo3::exceptions::PyException;
+
+create_exception!(kompyle, KompyleError, PyException, "Base class of all kompyle errors.");
+create_exception!(kompyle, ParseError, KompyleError, "A d4 or DIMACS file is malformed.");
+create_exception!(kompyle, CompilationError, KompyleError, "The knowledge compiler failed.");
+create_exception!(kompyle, NumericalError, KompyleError, "A computation produced NaN, or the evidence has probability zero.");
+create_exception!(kompyle, InvalidCircuitError, KompyleError, "The circuit does not support the operation.");
+
+/// Errors of the Rust side, which become the matching Python exception.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(String),
+    Compilation(String),
+    // The compiler or its temporary files could not be run or written
+    CompilationIo(io::Error),
+    Numerical(String),
+    InvalidCircuit(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e.into(),
+            Error::Parse(msg) => ParseError::new_err(msg),
+            Error::Compilation(msg) => CompilationError::new_err(msg),
+            Error::CompilationIo(e) => Python::with_gil(|py| {
+                let err = CompilationError::new_err(format!("The compiler could not run: {}", e));
+                err.set_cause(py, Some(e.into()));
+                err
+            }),
+            Error::Numerical(msg) => NumericalError::new_err(msg),
+            Error::InvalidCircuit(msg) => InvalidCircuitError::new_err(msg),
+        }
+    }
+}
+
+/// The semiring operators can't fail, so NaNs propagate to the result and are reported here.
+pub fn check_nan<'a, F: Float + 'a>(values: impl IntoIterator<Item = &'a F>, what: &str) -> Result<()> {
+    if values.into_iter().any(|x| x.is_nan()) {
+        Err(Error::Numerical(format!("The {} is NaN", what)))
+    } else {
+        Ok(())
+    }
+}
@@ -1,14 +1,26 @@
+// pyo3 0.18 expands #[pymethods] into non-local impls.
+#![allow(non_local_definitions)]
+// Semiring operators intentionally implement e.g. `+` as max or `|`.
+#![allow(clippy::suspicious_arithmetic_impl)]
+#![allow(clippy::module_inception)]
+
 mod circuit;
 mod algebra;
+mod error;
 
 use pyo3::prelude::*;
 use circuit::circuit::Circuit;
+use circuit::incremental::IncrementalEvaluator;
+use circuit::models::ModelIterator;
 use circuit::rcircuit::RCircuit;
+use circuit::tape::Tape;
+use circuit::validate::ValidationReport;
 use circuit::parser::{load_d4, load_dimacs};
+use error::{KompyleError, ParseError, CompilationError, NumericalError, InvalidCircuitError};
 
 
 #[pymodule]
-fn kompyle(_py: Python, m: &PyModule) -> PyResult<()> {
+fn kompyle(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(load_d4, m)?)?;
     m.add_function(wrap_pyfunction!(circuit::zero, m)?)?;
     m.add_function(wrap_pyfunction!(circuit::one, m)?)?;
@@ -17,5 +29,15 @@ fn kompyle(_py: Python, m: &PyModule) -> PyResult<()> {
 
     m.add_class::<Circuit>()?;
     m.add_class::<RCircuit>()?;
+    m.add_class::<Tape>()?;
+    m.add_class::<ModelIterator>()?;
+    m.add_class::<IncrementalEvaluator>()?;
+    m.add_class::<ValidationReport>()?;
+
+    m.add("KompyleError", py.get_type::<KompyleError>())?;
+    m.add("ParseError", py.get_type::<ParseError>())?;
+    m.add("CompilationError", py.get_type::<CompilationError>())?;
+    m.add("NumericalError", py.get_type::<NumericalError>())?;
+    m.add("InvalidCircuitError", py.get_type::<InvalidCircuitError>())?;
     Ok(())
 }